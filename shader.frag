@@ -1,9 +1,49 @@
 #version 450
 
+// Transfer function expected by the presentation engine, see
+// `surface_format::OutputTransfer`: 0 none, 1 sRGB, 2 Rec. 2020 with PQ.
+layout(constant_id = 0) const uint OUTPUT_TRANSFER = 0;
+
+// Luminance of SDR white in HDR10 output, in nits.
+const float PAPER_WHITE = 203.0;
+
+// Columns are the Rec. 2020 coordinates of the Rec. 709 primaries.
+const mat3 REC709_TO_REC2020 = mat3(
+    0.6274, 0.0691, 0.0164,
+    0.3293, 0.9195, 0.0880,
+    0.0433, 0.0114, 0.8956
+);
+
 layout(location = 0) in vec3 fragColor;
 
 layout(location = 0) out vec4 outColor;
 
+vec3 srgb_oetf(vec3 c) {
+    vec3 low = c * 12.92;
+    vec3 high = 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055;
+    return mix(low, high, step(vec3(0.0031308), c));
+}
+
+// ST 2084 inverse EOTF, `c` in units of 10000 nits.
+vec3 pq_oetf(vec3 c) {
+    const float m1 = 0.1593017578125;
+    const float m2 = 78.84375;
+    const float c1 = 0.8359375;
+    const float c2 = 18.8515625;
+    const float c3 = 18.6875;
+
+    vec3 p = pow(max(c, vec3(0.0)), vec3(m1));
+    return pow((c1 + c2 * p) / (1.0 + c3 * p), vec3(m2));
+}
+
 void main() {
-    outColor = vec4(fragColor, 1.0);
+    vec3 color = fragColor;
+
+    if (OUTPUT_TRANSFER == 1) {
+        color = srgb_oetf(color);
+    } else if (OUTPUT_TRANSFER == 2) {
+        color = pq_oetf(REC709_TO_REC2020 * color * (PAPER_WHITE / 10000.0));
+    }
+
+    outColor = vec4(color, 1.0);
 }
@@ -15,6 +15,14 @@ vec3 colors[3] = vec3[](
 );
 
 void main() {
-    gl_Position = vec4(positions[gl_VertexIndex], 0.0, 1.0);
-    fragColor = colors[gl_VertexIndex];
+    vec2 position = positions[gl_VertexIndex];
+
+    // Instances from 1 are laid out in a 4x4 grid, see `scene::Scene`.
+    if (gl_InstanceIndex > 0) {
+        int cell = gl_InstanceIndex - 1;
+        position = position * 0.25 + vec2(cell % 4, cell / 4) * 0.5 - 0.75;
+    }
+
+    gl_Position = vec4(position, 0.0, 1.0);
+    fragColor = colors[(gl_VertexIndex + gl_InstanceIndex) % 3];
 }
@@ -0,0 +1,488 @@
+//! Pipeline barriers and queue submission using `VK_KHR_synchronization2`
+//! (core in 1.3), with a fallback to the original commands.
+//!
+//! Barriers are always described with the synchronization2 stage and access
+//! flags. Without synchronization2 they are converted to the closest legacy
+//! flags and recorded as a single `vkCmdPipelineBarrier`.
+
+use ash::vk;
+use ash::Device;
+
+use crate::AppData;
+
+#[derive(Copy, Clone, Debug)]
+pub struct ImageBarrier {
+    pub image: vk::Image,
+    pub range: vk::ImageSubresourceRange,
+    pub src_stage: vk::PipelineStageFlags2,
+    pub src_access: vk::AccessFlags2,
+    pub dst_stage: vk::PipelineStageFlags2,
+    pub dst_access: vk::AccessFlags2,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+    pub src_queue_family: u32,
+    pub dst_queue_family: u32,
+}
+
+impl ImageBarrier {
+    /// A barrier on the first mip level and layer of a color image, without a
+    /// layout transition or access.
+    pub fn color(image: vk::Image) -> Self {
+        Self {
+            image,
+            range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            src_stage: vk::PipelineStageFlags2::NONE,
+            src_access: vk::AccessFlags2::NONE,
+            dst_stage: vk::PipelineStageFlags2::NONE,
+            dst_access: vk::AccessFlags2::NONE,
+            old_layout: vk::ImageLayout::UNDEFINED,
+            new_layout: vk::ImageLayout::UNDEFINED,
+            src_queue_family: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family: vk::QUEUE_FAMILY_IGNORED,
+        }
+    }
+
+    pub fn layout(mut self, old: vk::ImageLayout, new: vk::ImageLayout) -> Self {
+        self.old_layout = old;
+        self.new_layout = new;
+        self
+    }
+
+    pub fn src(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.src_stage = stage;
+        self.src_access = access;
+        self
+    }
+
+    pub fn dst(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.dst_stage = stage;
+        self.dst_access = access;
+        self
+    }
+
+    /// Transfers ownership from queue family `src` to `dst`.
+    ///
+    /// The barrier must be recorded on both queues: on `src` with the
+    /// destination stage and access cleared (release), and on `dst` with the
+    /// source stage and access cleared (acquire).
+    #[allow(dead_code)]
+    pub fn queue_transfer(mut self, src: u32, dst: u32) -> Self {
+        self.src_queue_family = src;
+        self.dst_queue_family = dst;
+        self
+    }
+
+    fn build(&self) -> vk::ImageMemoryBarrier2 {
+        vk::ImageMemoryBarrier2::builder()
+            .image(self.image)
+            .subresource_range(self.range)
+            .src_stage_mask(self.src_stage)
+            .src_access_mask(self.src_access)
+            .dst_stage_mask(self.dst_stage)
+            .dst_access_mask(self.dst_access)
+            .old_layout(self.old_layout)
+            .new_layout(self.new_layout)
+            .src_queue_family_index(self.src_queue_family)
+            .dst_queue_family_index(self.dst_queue_family)
+            .build()
+    }
+
+    fn build_legacy(&self) -> vk::ImageMemoryBarrier {
+        vk::ImageMemoryBarrier::builder()
+            .image(self.image)
+            .subresource_range(self.range)
+            .src_access_mask(legacy_access(self.src_access))
+            .dst_access_mask(legacy_access(self.dst_access))
+            .old_layout(self.old_layout)
+            .new_layout(self.new_layout)
+            .src_queue_family_index(self.src_queue_family)
+            .dst_queue_family_index(self.dst_queue_family)
+            .build()
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BufferBarrier {
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+    pub src_stage: vk::PipelineStageFlags2,
+    pub src_access: vk::AccessFlags2,
+    pub dst_stage: vk::PipelineStageFlags2,
+    pub dst_access: vk::AccessFlags2,
+    pub src_queue_family: u32,
+    pub dst_queue_family: u32,
+}
+
+impl BufferBarrier {
+    /// A barrier on the whole buffer without any access.
+    pub fn whole(buffer: vk::Buffer) -> Self {
+        Self {
+            buffer,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            src_stage: vk::PipelineStageFlags2::NONE,
+            src_access: vk::AccessFlags2::NONE,
+            dst_stage: vk::PipelineStageFlags2::NONE,
+            dst_access: vk::AccessFlags2::NONE,
+            src_queue_family: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family: vk::QUEUE_FAMILY_IGNORED,
+        }
+    }
+
+    pub fn src(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.src_stage = stage;
+        self.src_access = access;
+        self
+    }
+
+    pub fn dst(mut self, stage: vk::PipelineStageFlags2, access: vk::AccessFlags2) -> Self {
+        self.dst_stage = stage;
+        self.dst_access = access;
+        self
+    }
+
+    /// See [`ImageBarrier::queue_transfer`].
+    #[allow(dead_code)]
+    pub fn queue_transfer(mut self, src: u32, dst: u32) -> Self {
+        self.src_queue_family = src;
+        self.dst_queue_family = dst;
+        self
+    }
+
+    fn build(&self) -> vk::BufferMemoryBarrier2 {
+        vk::BufferMemoryBarrier2::builder()
+            .buffer(self.buffer)
+            .offset(self.offset)
+            .size(self.size)
+            .src_stage_mask(self.src_stage)
+            .src_access_mask(self.src_access)
+            .dst_stage_mask(self.dst_stage)
+            .dst_access_mask(self.dst_access)
+            .src_queue_family_index(self.src_queue_family)
+            .dst_queue_family_index(self.dst_queue_family)
+            .build()
+    }
+
+    fn build_legacy(&self) -> vk::BufferMemoryBarrier {
+        vk::BufferMemoryBarrier::builder()
+            .buffer(self.buffer)
+            .offset(self.offset)
+            .size(self.size)
+            .src_access_mask(legacy_access(self.src_access))
+            .dst_access_mask(legacy_access(self.dst_access))
+            .src_queue_family_index(self.src_queue_family)
+            .dst_queue_family_index(self.dst_queue_family)
+            .build()
+    }
+}
+
+pub unsafe fn pipeline_barrier(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    buffers: &[BufferBarrier],
+    images: &[ImageBarrier],
+) {
+    if buffers.is_empty() && images.is_empty() {
+        return;
+    }
+
+    if data.synchronization2 {
+        let buffer_barriers = buffers.iter().map(|b| b.build()).collect::<Vec<_>>();
+        let image_barriers = images.iter().map(|b| b.build()).collect::<Vec<_>>();
+
+        let info = vk::DependencyInfo::builder()
+            .buffer_memory_barriers(&buffer_barriers)
+            .image_memory_barriers(&image_barriers);
+
+        match &data.synchronization2_ext {
+            Some(ext) => ext.cmd_pipeline_barrier2(command_buffer, &info),
+            None => device.cmd_pipeline_barrier2(command_buffer, &info),
+        }
+
+        return;
+    }
+
+    let mut src_stage = vk::PipelineStageFlags2::NONE;
+    let mut dst_stage = vk::PipelineStageFlags2::NONE;
+    for (src, dst) in buffers
+        .iter()
+        .map(|b| (b.src_stage, b.dst_stage))
+        .chain(images.iter().map(|b| (b.src_stage, b.dst_stage)))
+    {
+        src_stage |= src;
+        dst_stage |= dst;
+    }
+
+    let buffer_barriers = buffers.iter().map(|b| b.build_legacy()).collect::<Vec<_>>();
+    let image_barriers = images.iter().map(|b| b.build_legacy()).collect::<Vec<_>>();
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        legacy_stages(src_stage, vk::PipelineStageFlags::TOP_OF_PIPE),
+        legacy_stages(dst_stage, vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+        vk::DependencyFlags::empty(),
+        &[],
+        &buffer_barriers,
+        &image_barriers,
+    );
+}
+
+/// A semaphore waited on or signaled by a submission.
+#[derive(Copy, Clone, Debug)]
+pub struct SemaphoreSubmit {
+    pub semaphore: vk::Semaphore,
+    pub stage: vk::PipelineStageFlags2,
+    /// Value for timeline semaphores, ignored for binary semaphores.
+    pub value: u64,
+}
+
+impl SemaphoreSubmit {
+    pub fn binary(semaphore: vk::Semaphore, stage: vk::PipelineStageFlags2) -> Self {
+        Self {
+            semaphore,
+            stage,
+            value: 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Submit<'a> {
+    pub wait: &'a [SemaphoreSubmit],
+    pub command_buffers: &'a [vk::CommandBuffer],
+    pub signal: &'a [SemaphoreSubmit],
+}
+
+pub unsafe fn queue_submit(
+    device: &Device,
+    data: &AppData,
+    queue: vk::Queue,
+    submits: &[Submit],
+    fence: vk::Fence,
+) -> ash::prelude::VkResult<()> {
+    if data.synchronization2 {
+        let semaphore_info = |s: &SemaphoreSubmit| {
+            vk::SemaphoreSubmitInfo::builder()
+                .semaphore(s.semaphore)
+                .stage_mask(s.stage)
+                .value(s.value)
+                .build()
+        };
+
+        let infos = submits
+            .iter()
+            .map(|s| {
+                let wait = s.wait.iter().map(semaphore_info).collect::<Vec<_>>();
+                let command_buffers = s
+                    .command_buffers
+                    .iter()
+                    .map(|c| {
+                        vk::CommandBufferSubmitInfo::builder()
+                            .command_buffer(*c)
+                            .build()
+                    })
+                    .collect::<Vec<_>>();
+                let signal = s.signal.iter().map(semaphore_info).collect::<Vec<_>>();
+
+                (wait, command_buffers, signal)
+            })
+            .collect::<Vec<_>>();
+
+        let submits = infos
+            .iter()
+            .map(|(wait, command_buffers, signal)| {
+                vk::SubmitInfo2::builder()
+                    .wait_semaphore_infos(wait)
+                    .command_buffer_infos(command_buffers)
+                    .signal_semaphore_infos(signal)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+
+        return match &data.synchronization2_ext {
+            Some(ext) => ext.queue_submit2(queue, &submits, fence),
+            None => device.queue_submit2(queue, &submits, fence),
+        };
+    }
+
+    struct Legacy {
+        wait: Vec<vk::Semaphore>,
+        wait_stages: Vec<vk::PipelineStageFlags>,
+        wait_values: Vec<u64>,
+        signal: Vec<vk::Semaphore>,
+        signal_values: Vec<u64>,
+    }
+
+    let legacy = submits
+        .iter()
+        .map(|s| Legacy {
+            wait: s.wait.iter().map(|w| w.semaphore).collect(),
+            wait_stages: s
+                .wait
+                .iter()
+                .map(|w| legacy_stages(w.stage, vk::PipelineStageFlags::TOP_OF_PIPE))
+                .collect(),
+            wait_values: s.wait.iter().map(|w| w.value).collect(),
+            signal: s.signal.iter().map(|w| w.semaphore).collect(),
+            signal_values: s.signal.iter().map(|w| w.value).collect(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut timeline_infos = legacy
+        .iter()
+        .map(|l| {
+            vk::TimelineSemaphoreSubmitInfo::builder()
+                .wait_semaphore_values(&l.wait_values)
+                .signal_semaphore_values(&l.signal_values)
+                .build()
+        })
+        .collect::<Vec<_>>();
+
+    let infos = submits
+        .iter()
+        .zip(&legacy)
+        .zip(&mut timeline_infos)
+        .map(|((s, l), timeline)| {
+            let mut info = vk::SubmitInfo::builder()
+                .wait_semaphores(&l.wait)
+                .wait_dst_stage_mask(&l.wait_stages)
+                .command_buffers(s.command_buffers)
+                .signal_semaphores(&l.signal);
+
+            // Timeline values are only needed if a timeline semaphore is used.
+            if l.wait_values
+                .iter()
+                .chain(&l.signal_values)
+                .any(|v| *v != 0)
+            {
+                info = info.push_next(timeline);
+            }
+
+            info.build()
+        })
+        .collect::<Vec<_>>();
+
+    device.queue_submit(queue, &infos, fence)
+}
+
+/// Converts synchronization2 stages to legacy stages. `none` is used if no
+/// stage is set, which is not allowed without synchronization2.
+fn legacy_stages(
+    stages: vk::PipelineStageFlags2,
+    none: vk::PipelineStageFlags,
+) -> vk::PipelineStageFlags {
+    if stages == vk::PipelineStageFlags2::NONE {
+        return none;
+    }
+
+    // The bits of stages that exist in both versions are the same.
+    let mut legacy = vk::PipelineStageFlags::from_raw(stages.as_raw() as u32);
+
+    if stages.intersects(
+        vk::PipelineStageFlags2::COPY
+            | vk::PipelineStageFlags2::RESOLVE
+            | vk::PipelineStageFlags2::BLIT
+            | vk::PipelineStageFlags2::CLEAR,
+    ) {
+        legacy |= vk::PipelineStageFlags::TRANSFER;
+    }
+
+    if stages.intersects(
+        vk::PipelineStageFlags2::INDEX_INPUT | vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+    ) {
+        legacy |= vk::PipelineStageFlags::VERTEX_INPUT;
+    }
+
+    if stages.contains(vk::PipelineStageFlags2::PRE_RASTERIZATION_SHADERS) {
+        legacy |= vk::PipelineStageFlags::VERTEX_SHADER
+            | vk::PipelineStageFlags::TESSELLATION_CONTROL_SHADER
+            | vk::PipelineStageFlags::TESSELLATION_EVALUATION_SHADER
+            | vk::PipelineStageFlags::GEOMETRY_SHADER;
+    }
+
+    legacy
+}
+
+fn legacy_access(access: vk::AccessFlags2) -> vk::AccessFlags {
+    // The bits of accesses that exist in both versions are the same.
+    let mut legacy = vk::AccessFlags::from_raw(access.as_raw() as u32);
+
+    if access
+        .intersects(vk::AccessFlags2::SHADER_SAMPLED_READ | vk::AccessFlags2::SHADER_STORAGE_READ)
+    {
+        legacy |= vk::AccessFlags::SHADER_READ;
+    }
+
+    if access.contains(vk::AccessFlags2::SHADER_STORAGE_WRITE) {
+        legacy |= vk::AccessFlags::SHADER_WRITE;
+    }
+
+    legacy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_stages_use_none_if_empty() {
+        assert_eq!(
+            legacy_stages(
+                vk::PipelineStageFlags2::NONE,
+                vk::PipelineStageFlags::TOP_OF_PIPE
+            ),
+            vk::PipelineStageFlags::TOP_OF_PIPE
+        );
+    }
+
+    #[test]
+    fn legacy_stages_keep_shared_bits() {
+        assert_eq!(
+            legacy_stages(
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+                    | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TOP_OF_PIPE
+            ),
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::FRAGMENT_SHADER
+        );
+    }
+
+    #[test]
+    fn legacy_stages_map_split_stages() {
+        let none = vk::PipelineStageFlags::TOP_OF_PIPE;
+        let cases = [
+            (
+                vk::PipelineStageFlags2::COPY,
+                vk::PipelineStageFlags::TRANSFER,
+            ),
+            (
+                vk::PipelineStageFlags2::BLIT | vk::PipelineStageFlags2::CLEAR,
+                vk::PipelineStageFlags::TRANSFER,
+            ),
+            (
+                vk::PipelineStageFlags2::INDEX_INPUT,
+                vk::PipelineStageFlags::VERTEX_INPUT,
+            ),
+            (
+                vk::PipelineStageFlags2::PRE_RASTERIZATION_SHADERS,
+                vk::PipelineStageFlags::VERTEX_SHADER
+                    | vk::PipelineStageFlags::TESSELLATION_CONTROL_SHADER
+                    | vk::PipelineStageFlags::TESSELLATION_EVALUATION_SHADER
+                    | vk::PipelineStageFlags::GEOMETRY_SHADER,
+            ),
+        ];
+
+        for (stages, legacy) in cases {
+            assert_eq!(legacy_stages(stages, none), legacy, "{:?}", stages);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Diagnostics for lost devices.
+//!
+//! Render graphs recorded into the frame command buffers write a marker into a
+//! host-visible buffer before and after every pass. When the device is lost,
+//! the markers of the frames that did not complete show which passes the GPU
+//! started and finished.
+//!
+//! Markers are written with `VK_AMD_buffer_marker` if supported, once the
+//! preceding commands reached the top or bottom of the pipeline. Otherwise
+//! only the start markers are written, with `vkCmdFillBuffer`: they are not
+//! ordered with the commands of the passes, and ordering the finish markers
+//! would need a barrier after every pass.
+
+use std::collections::VecDeque;
+
+use ash::vk;
+use ash::{Device, Instance};
+
+use crate::memory::create_buffer;
+use crate::owned::{Owned, SharedDevice};
+use crate::{debug, AppData};
+
+/// Maximum number of passes with markers in a command buffer.
+const MAX_PASSES: usize = 64;
+
+/// Number of submitted frames kept for the report.
+const FRAME_HISTORY: usize = 8;
+
+/// Written before a pass.
+const STARTED: u32 = 1;
+/// Written after a pass.
+const FINISHED: u32 = 2;
+
+/// What to do when the device is lost.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeviceLostMode {
+    /// Log the report and panic.
+    #[default]
+    Panic,
+    /// Log the report, then destroy and create the app again.
+    Recreate,
+}
+
+impl DeviceLostMode {
+    /// Parses `panic` or `recreate`.
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "recreate" => Some(Self::Recreate),
+            "panic" => Some(Self::Panic),
+            _ => None,
+        }
+    }
+}
+
+/// A marker written around a pass, see [`Breadcrumbs::mark`].
+#[derive(Copy, Clone, Debug)]
+pub enum Marker {
+    /// Before the pass.
+    Started,
+    /// After the pass. Only written with `VK_AMD_buffer_marker`.
+    Finished,
+}
+
+/// A frame submitted to the graphics queue.
+#[derive(Copy, Clone, Debug)]
+struct SubmittedFrame {
+    frame: u64,
+    image_index: usize,
+    /// Graphics timeline value signaled by the frame.
+    value: u64,
+}
+
+/// Pass markers of the frame command buffers, one slot per command buffer.
+pub struct Breadcrumbs {
+    buffer: Owned<vk::Buffer>,
+    /// Persistently mapped, unmapped when freed.
+    #[allow(dead_code)]
+    memory: Owned<vk::DeviceMemory>,
+    markers: *mut u32,
+    slots: usize,
+    frames: VecDeque<SubmittedFrame>,
+}
+
+impl Breadcrumbs {
+    pub unsafe fn new(
+        instance: &Instance,
+        device: &SharedDevice,
+        data: &AppData,
+        slots: usize,
+    ) -> Self {
+        let size = (slots * MAX_PASSES * 4) as vk::DeviceSize;
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &[],
+        );
+
+        debug::set_object_name(device, data, *buffer, "breadcrumbs");
+        debug::set_object_name(device, data, *memory, "breadcrumbs memory");
+
+        let markers = device
+            .map_memory(*memory, 0, size, vk::MemoryMapFlags::empty())
+            .unwrap() as *mut u32;
+        std::ptr::write_bytes(markers, 0, slots * MAX_PASSES);
+
+        Self {
+            buffer,
+            memory,
+            markers,
+            slots,
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Records `marker` for the pass at `position` in the execution order.
+    pub unsafe fn mark(
+        &self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        position: usize,
+        marker: Marker,
+    ) {
+        if position >= MAX_PASSES {
+            return;
+        }
+
+        let offset = ((slot * MAX_PASSES + position) * 4) as vk::DeviceSize;
+        let (value, stage) = match marker {
+            Marker::Started => (STARTED, vk::PipelineStageFlags::TOP_OF_PIPE),
+            Marker::Finished => (FINISHED, vk::PipelineStageFlags::BOTTOM_OF_PIPE),
+        };
+
+        match (&data.buffer_marker_ext, marker) {
+            (Some(ext), _) => (ext.cmd_write_buffer_marker_amd)(
+                command_buffer,
+                stage,
+                *self.buffer,
+                offset,
+                value,
+            ),
+            (None, Marker::Started) => {
+                device.cmd_fill_buffer(command_buffer, *self.buffer, offset, 4, value)
+            }
+            (None, Marker::Finished) => (),
+        }
+    }
+
+    /// Clears the markers of `slot` before its command buffer is submitted.
+    /// The command buffer must not be in use.
+    pub unsafe fn reset(&self, slot: usize) {
+        std::ptr::write_bytes(self.slot(slot), 0, MAX_PASSES);
+    }
+
+    /// Records the submission of the command buffer of `slot` for `frame`,
+    /// signaling `value` on the graphics timeline.
+    pub fn submitted(&mut self, slot: usize, frame: u64, value: u64) {
+        if self.frames.len() == FRAME_HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(SubmittedFrame {
+            frame,
+            image_index: slot,
+            value,
+        });
+    }
+
+    unsafe fn slot(&self, slot: usize) -> *mut u32 {
+        assert!(slot < self.slots);
+        self.markers.add(slot * MAX_PASSES)
+    }
+
+    /// Logs the recently submitted frames and how far the GPU got with the
+    /// ones that did not complete.
+    pub unsafe fn report(&self, device: &Device, data: &AppData) {
+        // Frames may have completed since the timeline was last queried. The
+        // query may fail once the device is lost.
+        let completed = data
+            .graphics_timeline
+            .completed(device, data)
+            .unwrap_or_else(|err| {
+                tracing::warn!("failed to query the graphics timeline: {}", err);
+                data.graphics_timeline.last_completed()
+            });
+        tracing::error!(
+            "device lost, graphics timeline reached {} of {}",
+            completed,
+            data.graphics_timeline.submitted()
+        );
+
+        for (i, frame) in self.frames.iter().enumerate() {
+            if frame.value <= completed {
+                tracing::error!("frame {}: completed", frame.frame);
+                continue;
+            }
+
+            tracing::error!(
+                "frame {}: not completed, image {}, timeline value {}",
+                frame.frame,
+                frame.image_index,
+                frame.value
+            );
+
+            // The markers of the slot were overwritten by a later frame.
+            if self
+                .frames
+                .iter()
+                .skip(i + 1)
+                .any(|f| f.image_index == frame.image_index)
+            {
+                continue;
+            }
+
+            let Some(transients) = data.graph_transients.get(frame.image_index) else {
+                continue;
+            };
+
+            let mut passes = transients
+                .description()
+                .passes
+                .iter()
+                .filter_map(|p| Some((p.order?, p.name)))
+                .collect::<Vec<_>>();
+            passes.sort();
+
+            let markers = std::slice::from_raw_parts(self.slot(frame.image_index), MAX_PASSES);
+            for (position, name) in passes {
+                let state = match markers.get(position) {
+                    Some(&FINISHED) => "finished",
+                    Some(&STARTED) if data.buffer_marker_ext.is_none() => {
+                        "started, finish not tracked"
+                    }
+                    Some(&STARTED) => "started",
+                    Some(_) => "not started",
+                    None => "unknown",
+                };
+                tracing::error!("  pass #{} {:?}: {}", position, name, state);
+            }
+        }
+    }
+}
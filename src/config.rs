@@ -0,0 +1,665 @@
+//! Settings of the renderer.
+//!
+//! Every setting is taken from the command line, the environment or the
+//! configuration file, in that order of precedence, falling back to its
+//! default. The file is read from `--config <path>` or `HELLO_VULKAN_CONFIG`,
+//! or from `hello-vulkan.toml` in the working directory if it exists, with a
+//! table per setting group. Lists are arrays of strings in the file and comma
+//! separated on the command line and in the environment. `--print-config`
+//! prints the resolved settings in the format of the file and exits.
+//!
+//! Invalid options, files and values are returned as errors, reported by
+//! `main` before exiting with status 2. Unknown names of modes and policies
+//! fall back to their default with a warning.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ash::vk;
+
+use crate::breadcrumbs::DeviceLostMode;
+use crate::debug::ValidationConfig;
+use crate::pacing::{PacingConfig, RedrawMode};
+use crate::present;
+use crate::scene::Scene;
+use crate::surface_format::FormatPolicy;
+
+/// Read if no configuration file is given.
+const DEFAULT_FILE: &str = "hello-vulkan.toml";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    String,
+    Number,
+    /// Comma separated on the command line and in the environment.
+    List,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::String => "a string",
+            Self::Number => "a number",
+            Self::List => "an array of strings",
+        }
+    }
+}
+
+/// The value of a setting. Numbers are kept as written and parsed when the
+/// config is resolved.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    String(String),
+    List(Vec<String>),
+}
+
+impl Value {
+    /// Parses a value from the command line or the environment.
+    fn from_arg(kind: Kind, arg: &str) -> Self {
+        match kind {
+            Kind::String | Kind::Number => Self::String(arg.to_owned()),
+            Kind::List => Self::List(
+                arg.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Converts a value of the file, which must match `kind`.
+    fn from_toml(kind: Kind, value: &toml::Value) -> Result<Self, String> {
+        let invalid = || format!("expected {}, found {}", kind.name(), value.type_str());
+
+        match (kind, value) {
+            (Kind::String, toml::Value::String(string)) => Ok(Self::String(string.clone())),
+            (Kind::Number, toml::Value::Integer(number)) => Ok(Self::String(number.to_string())),
+            (Kind::Number, toml::Value::Float(number)) => Ok(Self::String(number.to_string())),
+            (Kind::List, toml::Value::Array(items)) => items
+                .iter()
+                .map(|item| item.as_str().map(str::to_owned).ok_or_else(invalid))
+                .collect::<Result<_, _>>()
+                .map(Self::List),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+struct Setting {
+    /// `<table>.<key>` in the file.
+    key: &'static str,
+    flag: &'static str,
+    var: &'static str,
+    kind: Kind,
+    default: Option<&'static str>,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "app.name",
+        flag: "--app-name",
+        var: "HELLO_VULKAN_APP_NAME",
+        kind: Kind::String,
+        default: Some("Hello Vulkan"),
+    },
+    Setting {
+        key: "window.width",
+        flag: "--width",
+        var: "HELLO_VULKAN_WIDTH",
+        kind: Kind::Number,
+        default: Some("800"),
+    },
+    Setting {
+        key: "window.height",
+        flag: "--height",
+        var: "HELLO_VULKAN_HEIGHT",
+        kind: Kind::Number,
+        default: Some("600"),
+    },
+    Setting {
+        key: "device.name",
+        flag: "--device",
+        var: "HELLO_VULKAN_DEVICE",
+        kind: Kind::String,
+        default: None,
+    },
+    Setting {
+        key: "device.lost",
+        flag: "--device-lost",
+        var: "HELLO_VULKAN_DEVICE_LOST",
+        kind: Kind::String,
+        default: Some("panic"),
+    },
+    Setting {
+        key: "validation.mode",
+        flag: "--validation",
+        var: "HELLO_VULKAN_VALIDATION",
+        kind: Kind::String,
+        default: Some("log"),
+    },
+    Setting {
+        key: "validation.features",
+        flag: "--validation-features",
+        var: "HELLO_VULKAN_VALIDATION_FEATURES",
+        kind: Kind::List,
+        default: Some(""),
+    },
+    Setting {
+        key: "validation.suppress",
+        flag: "--suppress",
+        var: "HELLO_VULKAN_SUPPRESS",
+        kind: Kind::List,
+        default: Some(""),
+    },
+    Setting {
+        key: "present.modes",
+        flag: "--present-mode",
+        var: "HELLO_VULKAN_PRESENT_MODE",
+        kind: Kind::List,
+        default: Some("mailbox,fifo"),
+    },
+    Setting {
+        key: "surface.format",
+        flag: "--surface-format",
+        var: "HELLO_VULKAN_SURFACE_FORMAT",
+        kind: Kind::String,
+        default: Some("srgb"),
+    },
+    Setting {
+        key: "pacing.redraw",
+        flag: "--redraw",
+        var: "HELLO_VULKAN_REDRAW",
+        kind: Kind::String,
+        default: Some("continuous"),
+    },
+    Setting {
+        key: "pacing.max_fps",
+        flag: "--max-fps",
+        var: "HELLO_VULKAN_MAX_FPS",
+        kind: Kind::Number,
+        default: None,
+    },
+    Setting {
+        key: "scene.name",
+        flag: "--scene",
+        var: "HELLO_VULKAN_SCENE",
+        kind: Kind::String,
+        default: Some("triangle"),
+    },
+    Setting {
+        key: "shaders.vertex",
+        flag: "--vertex-shader",
+        var: "HELLO_VULKAN_VERTEX_SHADER",
+        kind: Kind::String,
+        default: None,
+    },
+    Setting {
+        key: "shaders.fragment",
+        flag: "--fragment-shader",
+        var: "HELLO_VULKAN_FRAGMENT_SHADER",
+        kind: Kind::String,
+        default: None,
+    },
+];
+
+#[derive(Copy, Clone, Debug)]
+enum Source {
+    CommandLine,
+    Environment,
+    File,
+    Default,
+}
+
+impl Source {
+    fn name(self) -> &'static str {
+        match self {
+            Self::CommandLine => "command line",
+            Self::Environment => "environment",
+            Self::File => "file",
+            Self::Default => "default",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Window title and application name given to the instance.
+    pub app_name: String,
+    /// Logical size of the window, or size of offscreen renders.
+    pub width: u32,
+    pub height: u32,
+    /// Index or case-insensitive name substring of the physical device to use,
+    /// otherwise the first suitable device is used.
+    pub device: Option<String>,
+    pub device_lost: DeviceLostMode,
+    pub validation: ValidationConfig,
+    /// Present modes in order of preference, see [`present`].
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    pub surface_format: FormatPolicy,
+    pub pacing: PacingConfig,
+    pub scene: Scene,
+    /// SPIR-V files used instead of the shaders built into the binary.
+    pub vertex_shader: Option<PathBuf>,
+    pub fragment_shader: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let values = SETTINGS
+            .iter()
+            .map(|s| (s.key, (default(s), Source::Default)))
+            .collect();
+
+        Self::resolve(&values).unwrap()
+    }
+}
+
+impl Config {
+    /// Loads the settings and returns them with the arguments that are not
+    /// settings. Unknown options are an error before the first positional
+    /// argument, the subcommand, and passed on after it. Prints the settings
+    /// and exits on `--print-config`.
+    pub fn load(args: &[String]) -> Result<(Self, Vec<String>), String> {
+        let args = Args::parse(args)?;
+
+        let config_path = args
+            .config_path
+            .or_else(|| std::env::var_os("HELLO_VULKAN_CONFIG").map(PathBuf::from))
+            .or_else(|| Some(PathBuf::from(DEFAULT_FILE)).filter(|p| p.exists()));
+        let file = match &config_path {
+            Some(path) => read_file(path)?,
+            None => HashMap::new(),
+        };
+
+        let values = merge(&args.options, |var| std::env::var(var).ok(), &file);
+
+        if args.print_config {
+            if let Some(path) = &config_path {
+                println!("# configuration file: {}", path.display());
+            }
+            print!("{}", format_settings(&values));
+            std::process::exit(0);
+        }
+
+        Ok((Self::resolve(&values)?, args.positional))
+    }
+
+    fn resolve(values: &HashMap<&str, (Option<Value>, Source)>) -> Result<Self, String> {
+        let get = |key: &str| match &values[key].0 {
+            Some(Value::String(value)) => Some(value.as_str()),
+            Some(Value::List(_)) => panic!("{} is not a list", key),
+            None => None,
+        };
+        let list = |key: &str| match &values[key].0 {
+            Some(Value::List(items)) => items.as_slice(),
+            Some(Value::String(_)) => panic!("{} is a list", key),
+            None => &[],
+        };
+        let number = |key: &str| {
+            get(key)
+                .map(|v| {
+                    v.parse::<f64>()
+                        .map_err(|_| format!("invalid number {:?} for {}", v, key))
+                })
+                .transpose()
+        };
+        let size = |key: &str| match get(key) {
+            Some(v) => match v.parse::<u32>() {
+                Ok(size) if size > 0 => Ok(size),
+                _ => Err(format!(
+                    "invalid size {:?} for {}, expected a positive integer",
+                    v, key
+                )),
+            },
+            None => Err(format!("{} is not set", key)),
+        };
+
+        let device_lost = get("device.lost").unwrap_or_default();
+        let device_lost = DeviceLostMode::parse(device_lost).unwrap_or_else(|| {
+            tracing::warn!("unknown device lost mode {:?}, using panic", device_lost);
+            DeviceLostMode::Panic
+        });
+
+        let surface_format = get("surface.format").unwrap_or_default();
+        let surface_format = FormatPolicy::parse(surface_format).unwrap_or_else(|| {
+            tracing::warn!(
+                "unknown surface format policy {:?}, using srgb",
+                surface_format
+            );
+            FormatPolicy::Srgb
+        });
+
+        let redraw = get("pacing.redraw").unwrap_or_default();
+        let redraw = RedrawMode::parse(redraw).unwrap_or_else(|| {
+            tracing::warn!("unknown redraw mode {:?}, using continuous", redraw);
+            RedrawMode::Continuous
+        });
+
+        let scene = get("scene.name").unwrap_or_default();
+        let scene = Scene::parse(scene).unwrap_or_else(|| {
+            tracing::warn!("unknown scene {:?}, using triangle", scene);
+            Scene::Triangle
+        });
+
+        let frame_interval = number("pacing.max_fps")?.and_then(|fps| {
+            if fps > 0.0 {
+                Some(Duration::from_secs_f64(1.0 / fps))
+            } else {
+                tracing::warn!("invalid FPS cap {}, ignoring", fps);
+                None
+            }
+        });
+
+        Ok(Self {
+            app_name: get("app.name").unwrap_or_default().to_owned(),
+            width: size("window.width")?,
+            height: size("window.height")?,
+            device: get("device.name").map(str::to_owned),
+            device_lost,
+            validation: ValidationConfig::parse(
+                get("validation.mode").unwrap_or_default(),
+                list("validation.features"),
+                list("validation.suppress"),
+            ),
+            present_modes: present::parse(list("present.modes")),
+            surface_format,
+            pacing: PacingConfig {
+                redraw,
+                frame_interval,
+            },
+            scene,
+            vertex_shader: get("shaders.vertex").map(PathBuf::from),
+            fragment_shader: get("shaders.fragment").map(PathBuf::from),
+        })
+    }
+}
+
+fn default(setting: &Setting) -> Option<Value> {
+    setting
+        .default
+        .map(|default| Value::from_arg(setting.kind, default))
+}
+
+/// The command line, split into settings and other arguments.
+#[derive(Debug, Default)]
+struct Args {
+    /// Settings by key.
+    options: HashMap<&'static str, Value>,
+    positional: Vec<String>,
+    config_path: Option<PathBuf>,
+    print_config: bool,
+}
+
+impl Args {
+    /// Parses `args`, the first of which is the program.
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut parsed = Self::default();
+
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("missing value for {}", arg))
+            };
+
+            if arg == "--config" {
+                parsed.config_path = Some(PathBuf::from(value()?));
+            } else if arg == "--print-config" {
+                parsed.print_config = true;
+            } else if let Some(setting) = SETTINGS.iter().find(|s| s.flag == arg) {
+                let value = Value::from_arg(setting.kind, &value()?);
+                parsed.options.insert(setting.key, value);
+            } else if arg.starts_with("--") && parsed.positional.is_empty() {
+                return Err(format!("unknown option {}", arg));
+            } else {
+                parsed.positional.push(arg.clone());
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Picks the value of every setting from the command line `options`, the
+/// environment `var`, the `file` or the default, in that order.
+fn merge(
+    options: &HashMap<&str, Value>,
+    var: impl Fn(&str) -> Option<String>,
+    file: &HashMap<&str, Value>,
+) -> HashMap<&'static str, (Option<Value>, Source)> {
+    SETTINGS
+        .iter()
+        .map(|s| {
+            let value = if let Some(value) = options.get(s.key) {
+                (Some(value.clone()), Source::CommandLine)
+            } else if let Some(value) = var(s.var) {
+                (Some(Value::from_arg(s.kind, &value)), Source::Environment)
+            } else if let Some(value) = file.get(s.key) {
+                (Some(value.clone()), Source::File)
+            } else {
+                (default(s), Source::Default)
+            };
+            (s.key, value)
+        })
+        .collect()
+}
+
+fn read_file(path: &Path) -> Result<HashMap<&'static str, Value>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+    parse_file(&text).map_err(|err| format!("failed to parse {}: {}", path.display(), err))
+}
+
+/// Parses a configuration file into values by setting. Unknown settings are
+/// ignored with a warning.
+fn parse_file(text: &str) -> Result<HashMap<&'static str, Value>, String> {
+    let file = text.parse::<toml::Table>().map_err(|err| err.to_string())?;
+
+    let mut values = HashMap::new();
+    for (table, entries) in &file {
+        let Some(entries) = entries.as_table() else {
+            tracing::warn!("unknown setting {}", table);
+            continue;
+        };
+
+        for (key, value) in entries {
+            let key = format!("{}.{}", table, key);
+            let Some(setting) = SETTINGS.iter().find(|s| s.key == key) else {
+                tracing::warn!("unknown setting {}", key);
+                continue;
+            };
+
+            let value =
+                Value::from_toml(setting.kind, value).map_err(|err| format!("{}: {}", key, err))?;
+            values.insert(setting.key, value);
+        }
+    }
+
+    Ok(values)
+}
+
+/// Formats the settings as a configuration file, with their sources.
+fn format_settings(values: &HashMap<&str, (Option<Value>, Source)>) -> String {
+    let mut out = String::new();
+    let mut current_table = "";
+    for setting in SETTINGS {
+        let (table, key) = setting.key.split_once('.').unwrap();
+        if table != current_table {
+            writeln!(out, "\n[{}]", table).unwrap();
+            current_table = table;
+        }
+
+        let (value, source) = &values[setting.key];
+        let value = match (setting.kind, value) {
+            (_, None) => {
+                writeln!(out, "# {} is not set", key).unwrap();
+                continue;
+            }
+            (Kind::Number, Some(Value::String(number))) => number.clone(),
+            (_, Some(Value::String(string))) => toml::Value::String(string.clone()).to_string(),
+            (_, Some(Value::List(items))) => {
+                let items = items.iter().cloned().map(toml::Value::String).collect();
+                toml::Value::Array(items).to_string()
+            }
+        };
+        writeln!(out, "{} = {} # {}", key, value, source.name()).unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        std::iter::once("hello-vulkan")
+            .chain(args.iter().copied())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    fn list(items: &[&str]) -> Value {
+        Value::List(items.iter().map(|item| item.to_string()).collect())
+    }
+
+    #[test]
+    fn parses_file() {
+        let values = parse_file(
+            r#"
+            # Comment
+            [window]
+            width = 1280 # Trailing comment
+            height = 720.0
+
+            [validation]
+            mode = "count"
+            suppress = ["VUID-a,b", '0x1234']
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(values["window.width"], Value::String("1280".to_owned()));
+        assert_eq!(values["window.height"], Value::String("720".to_owned()));
+        assert_eq!(values["validation.mode"], Value::String("count".to_owned()));
+        assert_eq!(values["validation.suppress"], list(&["VUID-a,b", "0x1234"]));
+        assert_eq!(values.len(), 4);
+    }
+
+    #[test]
+    fn ignores_unknown_settings() {
+        let values = parse_file("[window]\nunknown = 1\n[app]\nname = \"Test\"").unwrap();
+        assert_eq!(values.len(), 1);
+    }
+
+    #[test]
+    fn rejects_mismatched_types() {
+        let err = parse_file("[window]\nwidth = \"wide\"").unwrap_err();
+        assert!(err.contains("window.width"), "{}", err);
+
+        let err = parse_file("[present]\nmodes = \"fifo\"").unwrap_err();
+        assert!(err.contains("present.modes"), "{}", err);
+
+        assert!(parse_file("[window\nwidth = 1").is_err());
+    }
+
+    #[test]
+    fn keeps_commas_in_file_lists() {
+        let file = parse_file("[validation]\nsuppress = [\"a,b\"]").unwrap();
+        let config = Config::resolve(&merge(&HashMap::new(), |_| None, &file)).unwrap();
+        assert_eq!(config.validation.suppressed, ["a,b"]);
+    }
+
+    #[test]
+    fn splits_args() {
+        let parsed = Args::parse(&args(&[
+            "--width",
+            "1024",
+            "--present-mode",
+            "fifo, mailbox",
+            "render",
+            "--unknown",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            parsed.options["window.width"],
+            Value::String("1024".to_owned())
+        );
+        assert_eq!(parsed.options["present.modes"], list(&["fifo", "mailbox"]));
+        assert_eq!(parsed.positional, ["render", "--unknown"]);
+    }
+
+    #[test]
+    fn rejects_invalid_args() {
+        let err = Args::parse(&args(&["--unknown", "render"])).unwrap_err();
+        assert_eq!(err, "unknown option --unknown");
+
+        let err = Args::parse(&args(&["--width"])).unwrap_err();
+        assert_eq!(err, "missing value for --width");
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        let resolve = |command_line: &[&str]| {
+            let options = Args::parse(&args(command_line)).unwrap().options;
+            Config::resolve(&merge(&options, |_| None, &HashMap::new()))
+        };
+
+        for width in ["abc", "0", "-5", "1.5"] {
+            let err = resolve(&["--width", width]).unwrap_err();
+            assert!(err.contains("window.width"), "{}", err);
+        }
+        assert!(resolve(&["--height", "0"]).is_err());
+        assert!(resolve(&["--max-fps", "fast"]).is_err());
+        assert_eq!(resolve(&["--width", "1024"]).unwrap().width, 1024);
+    }
+
+    #[test]
+    fn rejects_unreadable_files() {
+        let err = read_file(Path::new("does/not/exist.toml")).unwrap_err();
+        assert!(err.contains("does/not/exist.toml"), "{}", err);
+    }
+
+    #[test]
+    fn merges_in_order_of_precedence() {
+        let options = Args::parse(&args(&["--width", "1"])).unwrap().options;
+        let file = parse_file("[window]\nwidth = 3\nheight = 3\n[app]\nname = \"file\"").unwrap();
+        let var = |var: &str| {
+            ["HELLO_VULKAN_WIDTH", "HELLO_VULKAN_HEIGHT"]
+                .contains(&var)
+                .then(|| "2".to_owned())
+        };
+
+        let values = merge(&options, var, &file);
+        let config = Config::resolve(&values).unwrap();
+
+        assert_eq!(config.width, 1);
+        assert_eq!(config.height, 2);
+        assert_eq!(config.app_name, "file");
+        assert_eq!(config.scene, Scene::Triangle);
+        assert!(matches!(values["window.width"].1, Source::CommandLine));
+        assert!(matches!(values["window.height"].1, Source::Environment));
+        assert!(matches!(values["app.name"].1, Source::File));
+        assert!(matches!(values["scene.name"].1, Source::Default));
+    }
+
+    #[test]
+    fn printed_settings_parse_back() {
+        let file = parse_file(
+            "[app]\nname = \"Quote \\\" and # hash\"\n[validation]\nsuppress = [\"a,b\", \"c\"]",
+        )
+        .unwrap();
+        let values = merge(&HashMap::new(), |_| None, &file);
+
+        let printed = parse_file(&format_settings(&values)).unwrap();
+        for (key, (value, _)) in &values {
+            assert_eq!(printed.get(key), value.as_ref(), "{}", key);
+        }
+    }
+}
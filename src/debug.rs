@@ -0,0 +1,363 @@
+//! Debug messenger, object names and command buffer labels via
+//! `VK_EXT_debug_utils`.
+//!
+//! Names show up in validation messages and in external debuggers such as
+//! RenderDoc. The naming and labelling functions are no-ops if the extension
+//! is not loaded.
+
+use std::ffi::{c_void, CStr, CString};
+use std::fmt::{self, Debug, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::owned::DeviceOwner;
+use crate::AppData;
+use ash::vk::{
+    self, Bool32, DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT,
+    DebugUtilsMessengerCallbackDataEXT, Handle,
+};
+
+/// What to do when the validation layer reports an error.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ErrorMode {
+    /// Only log the error.
+    #[default]
+    Log,
+    /// Log and count errors. The process exits with a failure status on
+    /// shutdown if any error was reported.
+    Count,
+    /// Panic on the first error. The callback is invoked from the driver and
+    /// can't unwind, so it only records the error and the panic happens in
+    /// [`DebugState::check`] after the Vulkan call that reported it.
+    Panic,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ValidationConfig {
+    /// Runs without the validation layer.
+    pub disabled: bool,
+    /// Message ID names (e.g. `VUID-vkCmdDraw-None-02859`) or numbers (e.g.
+    /// `0x1608dec0`) that are not reported.
+    pub suppressed: Vec<String>,
+    pub error_mode: ErrorMode,
+    /// Enables synchronization validation (hazards between commands).
+    pub synchronization: bool,
+    /// Enables GPU-assisted validation (instrumented shaders). Can't be used
+    /// together with `debug_printf`.
+    pub gpu_assisted: bool,
+    pub best_practices: bool,
+    /// Enables `debugPrintfEXT` in shaders. The output is logged with the
+    /// `shader` target.
+    pub debug_printf: bool,
+}
+
+impl ValidationConfig {
+    /// Parses the config from the settings, see [`config`](crate::config):
+    ///
+    /// - `mode`: `log`, `count`, `panic` or `off` to disable the layer.
+    /// - `features`: list of `sync`, `gpu-assisted`, `best-practices` and
+    ///   `debug-printf`.
+    /// - `suppressed`: list of suppressed messages.
+    pub fn parse(mode: &str, features: &[String], suppressed: &[String]) -> Self {
+        let suppressed = suppressed.to_vec();
+
+        let (error_mode, disabled) = match mode {
+            "count" => (ErrorMode::Count, false),
+            "panic" => (ErrorMode::Panic, false),
+            "log" => (ErrorMode::Log, false),
+            "off" => (ErrorMode::Log, true),
+            _ => {
+                tracing::warn!("unknown validation mode {:?}, using log", mode);
+                (ErrorMode::Log, false)
+            }
+        };
+
+        let mut config = Self {
+            disabled,
+            suppressed,
+            error_mode,
+            ..Default::default()
+        };
+
+        for feature in features {
+            match feature.as_str() {
+                "sync" => config.synchronization = true,
+                "gpu-assisted" => config.gpu_assisted = true,
+                "best-practices" => config.best_practices = true,
+                "debug-printf" => config.debug_printf = true,
+                "" => (),
+                _ => tracing::warn!("unknown validation feature {:?}", feature),
+            }
+        }
+
+        config
+    }
+
+    /// Returns the features to enable through `VkValidationFeaturesEXT`.
+    pub fn enabled_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut features = Vec::new();
+
+        if self.synchronization {
+            features.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+
+        if self.best_practices {
+            features.push(vk::ValidationFeatureEnableEXT::BEST_PRACTICES);
+        }
+
+        if self.debug_printf {
+            if self.gpu_assisted {
+                tracing::warn!(
+                    "GPU-assisted validation can't be used with debug printf, disabling it"
+                );
+            }
+
+            features.push(vk::ValidationFeatureEnableEXT::DEBUG_PRINTF);
+        } else if self.gpu_assisted {
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+
+        features
+    }
+}
+
+/// State shared with [`debug_callback`] through the messenger user data.
+#[derive(Debug, Default)]
+pub struct DebugState {
+    config: ValidationConfig,
+    errors: AtomicUsize,
+    /// The first error not yet raised by [`check`](Self::check) in panic mode.
+    pending: Mutex<Option<String>>,
+}
+
+impl DebugState {
+    pub fn new(config: ValidationConfig) -> Self {
+        Self {
+            config,
+            errors: AtomicUsize::new(0),
+            pending: Mutex::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &ValidationConfig {
+        &self.config
+    }
+
+    /// Returns the number of validation errors reported so far.
+    pub fn errors(&self) -> usize {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the run should fail because of counted validation
+    /// errors.
+    pub fn failed(&self) -> bool {
+        self.config.error_mode == ErrorMode::Count && self.errors() != 0
+    }
+
+    /// Panics if a validation error was reported in panic mode since the last
+    /// check. Called after Vulkan calls that may report errors.
+    pub fn check(&self) {
+        if let Some(message) = self.pending.lock().unwrap().take() {
+            panic!("validation error: {}", message);
+        }
+    }
+
+    fn is_suppressed(&self, name: Option<&str>, number: i32) -> bool {
+        self.config.suppressed.iter().any(|id| {
+            Some(id.as_str()) == name
+                || id
+                    .strip_prefix("0x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or_else(|| id.parse().ok())
+                    == Some(number as u32)
+        })
+    }
+}
+
+/// An object involved in a debug message.
+struct Object {
+    type_: vk::ObjectType,
+    handle: u64,
+    name: Option<String>,
+}
+
+impl Debug for Object {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}(0x{:x})", self.type_, self.handle)?;
+        if let Some(name) = &self.name {
+            write!(f, " {:?}", name)?;
+        }
+
+        Ok(())
+    }
+}
+
+unsafe fn read_labels(labels: *const vk::DebugUtilsLabelEXT, count: u32) -> Vec<String> {
+    if labels.is_null() {
+        return Vec::new();
+    }
+
+    std::slice::from_raw_parts(labels, count as usize)
+        .iter()
+        .filter(|l| !l.p_label_name.is_null())
+        .map(|l| {
+            CStr::from_ptr(l.p_label_name)
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect()
+}
+
+/// `user_data` must point to a [`DebugState`] or be null.
+pub extern "system" fn debug_callback(
+    severity: DebugUtilsMessageSeverityFlagsEXT,
+    type_: DebugUtilsMessageTypeFlagsEXT,
+    data: *const DebugUtilsMessengerCallbackDataEXT,
+    user_data: *mut c_void,
+) -> Bool32 {
+    let data = unsafe { *data };
+    let state = unsafe { (user_data as *const DebugState).as_ref() };
+
+    let message = unsafe { CStr::from_ptr(data.p_message) }.to_string_lossy();
+    let id_name = (!data.p_message_id_name.is_null())
+        .then(|| unsafe { CStr::from_ptr(data.p_message_id_name) }.to_string_lossy());
+    let id_number = data.message_id_number;
+
+    if let Some(state) = state {
+        if state.is_suppressed(id_name.as_deref(), id_number) {
+            return vk::FALSE;
+        }
+    }
+
+    let objects = if data.p_objects.is_null() {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(data.p_objects, data.object_count as usize) }
+            .iter()
+            .map(|o| Object {
+                type_: o.object_type,
+                handle: o.object_handle,
+                name: (!o.p_object_name.is_null()).then(|| {
+                    unsafe { CStr::from_ptr(o.p_object_name) }
+                        .to_string_lossy()
+                        .into_owned()
+                }),
+            })
+            .collect()
+    };
+
+    let queue_labels = unsafe { read_labels(data.p_queue_labels, data.queue_label_count) };
+    let cmd_labels = unsafe { read_labels(data.p_cmd_buf_labels, data.cmd_buf_label_count) };
+
+    let id_name = id_name.as_deref().unwrap_or_default();
+    let id_number = format!("0x{:08x}", id_number as u32);
+
+    // The message is prefixed with the location of the printf call, the output
+    // comes after the last separator.
+    if id_name.contains("DEBUG-PRINTF") {
+        let output = message.rsplit("| ").next().unwrap_or_default();
+        tracing::info!(target: "shader", ?cmd_labels, "{}", output);
+        return vk::FALSE;
+    }
+
+    if severity >= DebugUtilsMessageSeverityFlagsEXT::ERROR {
+        tracing::error!(
+            r#type = ?type_,
+            message_id = id_name,
+            message_id_number = %id_number,
+            ?objects,
+            ?queue_labels,
+            ?cmd_labels,
+            "{}",
+            message
+        );
+
+        if let Some(state) = state {
+            if type_.contains(DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+                state.errors.fetch_add(1, Ordering::Relaxed);
+
+                // Unwinding out of the callback would abort, the error is
+                // raised by `DebugState::check` instead.
+                if state.config.error_mode == ErrorMode::Panic {
+                    if let Ok(mut pending) = state.pending.lock() {
+                        pending.get_or_insert_with(|| message.clone().into_owned());
+                    }
+                }
+            }
+        }
+    } else if severity >= DebugUtilsMessageSeverityFlagsEXT::WARNING {
+        tracing::warn!(
+            r#type = ?type_,
+            message_id = id_name,
+            message_id_number = %id_number,
+            ?objects,
+            ?queue_labels,
+            ?cmd_labels,
+            "{}",
+            message
+        );
+    } else if severity >= DebugUtilsMessageSeverityFlagsEXT::INFO {
+        tracing::debug!(
+            r#type = ?type_,
+            message_id = id_name,
+            message_id_number = %id_number,
+            ?objects,
+            "{}",
+            message
+        );
+    } else {
+        tracing::trace!(
+            r#type = ?type_,
+            message_id = id_name,
+            message_id_number = %id_number,
+            ?objects,
+            "{}",
+            message
+        );
+    }
+
+    vk::FALSE
+}
+
+pub unsafe fn set_object_name<T: Handle>(
+    device: &DeviceOwner,
+    data: &AppData,
+    handle: T,
+    name: &str,
+) {
+    let handle = handle.as_raw();
+    device.registry().set_name(T::TYPE, handle, name);
+
+    let Some(debug_utils) = &data.debug_utils else {
+        return;
+    };
+
+    let name = CString::new(name).unwrap();
+    let info = vk::DebugUtilsObjectNameInfoEXT::builder()
+        .object_type(T::TYPE)
+        .object_handle(handle)
+        .object_name(&name);
+
+    debug_utils
+        .set_debug_utils_object_name(device.handle(), &info)
+        .unwrap();
+}
+
+/// Opens a label region in `command_buffer`, closed by [`end_label`].
+pub unsafe fn begin_label(data: &AppData, command_buffer: vk::CommandBuffer, name: &str) {
+    let Some(debug_utils) = &data.debug_utils else {
+        return;
+    };
+
+    let name = CString::new(name).unwrap();
+    let label = vk::DebugUtilsLabelEXT::builder().label_name(&name);
+
+    debug_utils.cmd_begin_debug_utils_label(command_buffer, &label);
+}
+
+pub unsafe fn end_label(data: &AppData, command_buffer: vk::CommandBuffer) {
+    if let Some(debug_utils) = &data.debug_utils {
+        debug_utils.cmd_end_debug_utils_label(command_buffer);
+    }
+}
@@ -0,0 +1,68 @@
+//! Deferred destruction of objects used by frames in flight.
+//!
+//! Objects that are replaced while submitted work may still use them (e.g. on
+//! swapchain recreation) or whose owner goes away (e.g. the view of a closed
+//! window) are retired into the [`DeletionQueue`] with a
+//! [`Timeline`](crate::timeline::Timeline) value, and dropped once the queue
+//! has reached it. The queue is shared by the renderer and its views.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+#[derive(Default)]
+pub struct DeletionQueue {
+    /// Retired objects, by the timeline value that must be reached before
+    /// they are dropped.
+    retired: RefCell<VecDeque<(u64, Box<dyn Any>)>>,
+}
+
+impl DeletionQueue {
+    /// Drops `object` once the timeline has reached `value`.
+    pub fn retire<T: Any>(&self, value: u64, object: T) {
+        self.retired
+            .borrow_mut()
+            .push_back((value, Box::new(object)));
+    }
+
+    /// Drops the objects whose value has been reached by `completed`.
+    pub fn collect(&self, completed: u64) {
+        // Dropped outside of the borrow, the objects may retire others.
+        let mut dropped = Vec::new();
+        {
+            let mut retired = self.retired.borrow_mut();
+            while let Some((value, _)) = retired.front() {
+                if *value > completed {
+                    break;
+                }
+
+                dropped.push(retired.pop_front().unwrap());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn drops_objects_once_their_value_is_reached() {
+        let queue = DeletionQueue::default();
+        let first = Rc::new(());
+        let second = Rc::new(());
+        queue.retire(1, first.clone());
+        queue.retire(3, second.clone());
+
+        queue.collect(0);
+        assert_eq!(Rc::strong_count(&first), 2);
+
+        queue.collect(2);
+        assert_eq!(Rc::strong_count(&first), 1);
+        assert_eq!(Rc::strong_count(&second), 2);
+
+        queue.collect(3);
+        assert_eq!(Rc::strong_count(&second), 1);
+    }
+}
@@ -0,0 +1,276 @@
+//! Vulkan API version negotiation and device feature queries.
+//!
+//! Features are described by [`Feature`] and requested as either required or
+//! optional. Devices missing a required feature are rejected, optional
+//! features are enabled if supported. The rest of the renderer checks
+//! [`DeviceFeatures::is_enabled`] before using a feature.
+//!
+//! Features promoted to core are also picked up from their extension on
+//! devices older than the promoting version.
+
+use std::ffi::CStr;
+
+use ash::vk;
+use ash::{Entry, Instance};
+
+use crate::read_cstr;
+
+/// Highest API version the renderer knows how to use.
+pub const MAX_API_VERSION: u32 = vk::API_VERSION_1_3;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Feature {
+    GeometryShader,
+    /// Required by GPU-assisted validation and debug printf in fragment
+    /// shaders.
+    FragmentStoresAndAtomics,
+    VertexPipelineStoresAndAtomics,
+    TimelineSemaphore,
+    Synchronization2,
+    DynamicRendering,
+}
+
+impl Feature {
+    /// Minimum device API version providing the feature in core.
+    fn api_version(self) -> u32 {
+        match self {
+            Self::GeometryShader
+            | Self::FragmentStoresAndAtomics
+            | Self::VertexPipelineStoresAndAtomics => vk::API_VERSION_1_0,
+            Self::TimelineSemaphore => vk::API_VERSION_1_2,
+            Self::Synchronization2 | Self::DynamicRendering => vk::API_VERSION_1_3,
+        }
+    }
+
+    /// Extension providing the feature before it was promoted to core.
+    fn extension(self) -> Option<&'static CStr> {
+        match self {
+            Self::TimelineSemaphore => Some(vk::KhrTimelineSemaphoreFn::name()),
+            Self::Synchronization2 => Some(vk::KhrSynchronization2Fn::name()),
+            Self::DynamicRendering => Some(vk::KhrDynamicRenderingFn::name()),
+            _ => None,
+        }
+    }
+
+    fn get(self, f: &DeviceFeatures) -> vk::Bool32 {
+        match self {
+            Self::GeometryShader => f.features.geometry_shader,
+            Self::FragmentStoresAndAtomics => f.features.fragment_stores_and_atomics,
+            Self::VertexPipelineStoresAndAtomics => f.features.vertex_pipeline_stores_and_atomics,
+            Self::TimelineSemaphore => {
+                f.vulkan12.timeline_semaphore | f.timeline_semaphore.timeline_semaphore
+            }
+            Self::Synchronization2 => {
+                f.vulkan13.synchronization2 | f.synchronization2.synchronization2
+            }
+            Self::DynamicRendering => {
+                f.vulkan13.dynamic_rendering | f.dynamic_rendering.dynamic_rendering
+            }
+        }
+    }
+
+    fn set(self, f: &mut DeviceFeatures) {
+        let field = match self {
+            Self::GeometryShader => &mut f.features.geometry_shader,
+            Self::FragmentStoresAndAtomics => &mut f.features.fragment_stores_and_atomics,
+            Self::VertexPipelineStoresAndAtomics => {
+                &mut f.features.vertex_pipeline_stores_and_atomics
+            }
+            Self::TimelineSemaphore if f.api_version < vk::API_VERSION_1_2 => {
+                &mut f.timeline_semaphore.timeline_semaphore
+            }
+            Self::TimelineSemaphore => &mut f.vulkan12.timeline_semaphore,
+            Self::Synchronization2 if f.api_version < vk::API_VERSION_1_3 => {
+                &mut f.synchronization2.synchronization2
+            }
+            Self::Synchronization2 => &mut f.vulkan13.synchronization2,
+            Self::DynamicRendering if f.api_version < vk::API_VERSION_1_3 => {
+                &mut f.dynamic_rendering.dynamic_rendering
+            }
+            Self::DynamicRendering => &mut f.vulkan13.dynamic_rendering,
+        };
+
+        *field = vk::TRUE;
+    }
+}
+
+/// Features requested from the device.
+pub struct FeatureRequest {
+    /// Devices without these features are not used.
+    pub required: &'static [Feature],
+    /// Enabled if supported.
+    pub optional: &'static [Feature],
+}
+
+/// A set of device features, either supported or enabled.
+#[derive(Clone, Default)]
+pub struct DeviceFeatures {
+    /// Effective API version of the device, limited by the instance version.
+    pub api_version: u32,
+    pub features: vk::PhysicalDeviceFeatures,
+    pub vulkan11: vk::PhysicalDeviceVulkan11Features,
+    pub vulkan12: vk::PhysicalDeviceVulkan12Features,
+    pub vulkan13: vk::PhysicalDeviceVulkan13Features,
+    /// `VK_KHR_timeline_semaphore`, only used below 1.2.
+    pub timeline_semaphore: vk::PhysicalDeviceTimelineSemaphoreFeatures,
+    /// `VK_KHR_synchronization2`, only used below 1.3.
+    pub synchronization2: vk::PhysicalDeviceSynchronization2Features,
+    /// `VK_KHR_dynamic_rendering`, only used below 1.3.
+    pub dynamic_rendering: vk::PhysicalDeviceDynamicRenderingFeatures,
+    /// Extensions providing features not in core for `api_version`.
+    pub extensions: Vec<&'static CStr>,
+}
+
+impl DeviceFeatures {
+    /// Queries the features supported by `physical_device`.
+    pub unsafe fn query(
+        instance: &Instance,
+        instance_version: u32,
+        physical_device: vk::PhysicalDevice,
+    ) -> Self {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let api_version = properties.api_version.min(instance_version);
+
+        let mut features = Self {
+            api_version,
+            ..Default::default()
+        };
+
+        if instance_version < vk::API_VERSION_1_1 {
+            features.features = instance.get_physical_device_features(physical_device);
+            return features;
+        }
+
+        let available = instance
+            .enumerate_device_extension_properties(physical_device)
+            .unwrap();
+
+        for feature in ALL {
+            if let Some(extension) = feature.extension() {
+                if api_version < feature.api_version()
+                    && available
+                        .iter()
+                        .any(|e| read_cstr(&e.extension_name) == extension)
+                {
+                    features.extensions.push(extension);
+                }
+            }
+        }
+
+        let mut features2 = features.chain();
+        instance.get_physical_device_features2(physical_device, &mut features2);
+        features.features = features2.features;
+
+        features.clear_chain();
+        features
+    }
+
+    /// Links the feature structs supported by `api_version` and `extensions`
+    /// into a chain for querying or device creation.
+    pub fn chain(&mut self) -> vk::PhysicalDeviceFeatures2Builder<'_> {
+        let mut features2 = vk::PhysicalDeviceFeatures2::builder().features(self.features);
+
+        // The VkPhysicalDeviceVulkan1xFeatures structs may only be chained if
+        // the device supports the version that introduced them.
+        if self.api_version >= vk::API_VERSION_1_2 {
+            features2 = features2
+                .push_next(&mut self.vulkan11)
+                .push_next(&mut self.vulkan12);
+        }
+        if self.api_version >= vk::API_VERSION_1_3 {
+            features2 = features2.push_next(&mut self.vulkan13);
+        }
+
+        if self
+            .extensions
+            .contains(&vk::KhrTimelineSemaphoreFn::name())
+        {
+            features2 = features2.push_next(&mut self.timeline_semaphore);
+        }
+        if self.extensions.contains(&vk::KhrSynchronization2Fn::name()) {
+            features2 = features2.push_next(&mut self.synchronization2);
+        }
+        if self.extensions.contains(&vk::KhrDynamicRenderingFn::name()) {
+            features2 = features2.push_next(&mut self.dynamic_rendering);
+        }
+
+        features2
+    }
+
+    fn clear_chain(&mut self) {
+        self.vulkan11.p_next = std::ptr::null_mut();
+        self.vulkan12.p_next = std::ptr::null_mut();
+        self.vulkan13.p_next = std::ptr::null_mut();
+        self.timeline_semaphore.p_next = std::ptr::null_mut();
+        self.synchronization2.p_next = std::ptr::null_mut();
+        self.dynamic_rendering.p_next = std::ptr::null_mut();
+    }
+
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        feature.get(self) == vk::TRUE
+    }
+
+    /// Returns the required features of `request` that are missing.
+    pub fn missing(&self, request: &FeatureRequest) -> Vec<Feature> {
+        request
+            .required
+            .iter()
+            .copied()
+            .filter(|f| !self.is_enabled(*f))
+            .collect()
+    }
+
+    /// Returns the features to enable for `request`, i.e. all required and the
+    /// supported optional features.
+    pub fn select(&self, request: &FeatureRequest) -> Self {
+        let mut enabled = Self {
+            api_version: self.api_version,
+            ..Default::default()
+        };
+
+        for feature in request.required.iter().chain(request.optional) {
+            if self.is_enabled(*feature) {
+                feature.set(&mut enabled);
+
+                if let Some(extension) = feature.extension() {
+                    if self.extensions.contains(&extension) {
+                        enabled.extensions.push(extension);
+                    }
+                }
+            }
+        }
+
+        enabled
+    }
+}
+
+/// Every feature known to [`DeviceFeatures`].
+pub const ALL: &[Feature] = &[
+    Feature::GeometryShader,
+    Feature::FragmentStoresAndAtomics,
+    Feature::VertexPipelineStoresAndAtomics,
+    Feature::TimelineSemaphore,
+    Feature::Synchronization2,
+    Feature::DynamicRendering,
+];
+
+/// Returns the API version to request from the instance, the highest version
+/// supported by the loader up to [`MAX_API_VERSION`].
+pub fn instance_version(entry: &Entry) -> u32 {
+    // Vulkan 1.0 loaders don't have vkEnumerateInstanceVersion.
+    let version = entry
+        .try_enumerate_instance_version()
+        .unwrap()
+        .unwrap_or(vk::API_VERSION_1_0);
+
+    version.min(MAX_API_VERSION)
+}
+
+pub fn format_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
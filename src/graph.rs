@@ -0,0 +1,1066 @@
+//! Render graph.
+//!
+//! Passes declare the resources they read and write and record their commands
+//! in a callback. When the graph is executed it
+//!
+//! - culls passes that don't contribute to an output, including writes
+//!   that a later pass overwrites,
+//! - orders the remaining passes by their dependencies,
+//! - creates the transient images, sharing memory between images that are
+//!   not used at the same time,
+//! - records the barriers between passes with a [`StateTracker`].
+//!
+//! The structure of the executed graph can be exported for debugging, see
+//! [`graph_export`](crate::graph_export).
+//!
+//! Passes depend on earlier declared passes that write a resource they use,
+//! or read a resource they write.
+
+use std::collections::{HashMap, HashSet};
+
+use ash::vk::{self, Handle};
+use ash::{Device, Instance};
+
+use crate::breadcrumbs::Marker;
+use crate::graph_export::{
+    BarrierDescription, GraphDescription, PassDescription, ResourceDescription, UseDescription,
+};
+use crate::memory::find_memory_type;
+use crate::owned::{Owned, SharedDevice};
+use crate::state::{Access, StateTracker};
+use crate::{debug, AppData};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+/// Description of a transient image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub usage: vk::ImageUsageFlags,
+    /// Render pass to create a framebuffer for, with the image as its only
+    /// attachment, see [`PassContext::framebuffer`]. May be null.
+    pub render_pass: vk::RenderPass,
+}
+
+enum Resource {
+    Image {
+        name: String,
+        image: vk::Image,
+        view: vk::ImageView,
+        extent: vk::Extent2D,
+        /// State before the graph and, for outputs, after the graph.
+        initial: Access,
+        output: Option<Access>,
+    },
+    Transient {
+        name: String,
+        desc: ImageDesc,
+    },
+    Buffer {
+        name: String,
+        buffer: vk::Buffer,
+        initial: Access,
+        output: Option<Access>,
+    },
+}
+
+impl Resource {
+    fn name(&self) -> &str {
+        match self {
+            Self::Image { name, .. } | Self::Transient { name, .. } | Self::Buffer { name, .. } => {
+                name
+            }
+        }
+    }
+
+    fn output(&self) -> Option<Access> {
+        match self {
+            Self::Image { output, .. } | Self::Buffer { output, .. } => *output,
+            Self::Transient { .. } => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Use {
+    resource: ResourceId,
+    access: Access,
+    /// The previous contents are not needed.
+    discard: bool,
+}
+
+impl Use {
+    fn writes(&self) -> bool {
+        self.access.is_write()
+    }
+}
+
+type Record<'a> = Box<dyn FnOnce(&PassContext) + 'a>;
+
+struct Pass<'a> {
+    name: &'static str,
+    uses: Vec<Use>,
+    record: Option<Record<'a>>,
+}
+
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+    resources: Vec<Resource>,
+    passes: Vec<Pass<'a>>,
+    /// Slot of `AppData::breadcrumbs` the pass markers are written to.
+    breadcrumbs: Option<usize>,
+    /// Slot of `AppData::gpu_timer` the pass timestamps are written to.
+    timestamps: Option<usize>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an image that is owned outside of the graph. `initial` is its state
+    /// before the graph is executed.
+    pub fn import_image(
+        &mut self,
+        name: &str,
+        image: vk::Image,
+        view: vk::ImageView,
+        extent: vk::Extent2D,
+        initial: Access,
+    ) -> ResourceId {
+        self.add(Resource::Image {
+            name: name.to_owned(),
+            image,
+            view,
+            extent,
+            initial,
+            output: None,
+        })
+    }
+
+    pub fn import_buffer(&mut self, name: &str, buffer: vk::Buffer, initial: Access) -> ResourceId {
+        self.add(Resource::Buffer {
+            name: name.to_owned(),
+            buffer,
+            initial,
+            output: None,
+        })
+    }
+
+    /// Adds an image that only lives while the graph is executed.
+    pub fn create_image(&mut self, name: &str, desc: ImageDesc) -> ResourceId {
+        self.add(Resource::Transient {
+            name: name.to_owned(),
+            desc,
+        })
+    }
+
+    /// Marks an imported resource as an output of the graph, which is left in
+    /// state `access`. Passes that don't contribute to an output are culled.
+    pub fn output(&mut self, resource: ResourceId, access: Access) {
+        match &mut self.resources[resource.0] {
+            Resource::Image { output, .. } | Resource::Buffer { output, .. } => {
+                *output = Some(access)
+            }
+            Resource::Transient { name, .. } => {
+                panic!("transient image {:?} can't be an output", name)
+            }
+        }
+    }
+
+    /// Writes markers before and after every pass to `slot` of the frame
+    /// breadcrumbs, see [`crate::breadcrumbs`].
+    pub fn breadcrumbs(&mut self, slot: usize) {
+        self.breadcrumbs = Some(slot);
+    }
+
+    /// Records a timestamp scope around every pass in `slot` of the GPU
+    /// timer, see [`crate::timing`].
+    pub fn timestamps(&mut self, slot: usize) {
+        self.timestamps = Some(slot);
+    }
+
+    fn add(&mut self, resource: Resource) -> ResourceId {
+        self.resources.push(resource);
+        ResourceId(self.resources.len() - 1)
+    }
+
+    /// Starts declaring a pass, added to the graph by [`PassBuilder::execute`].
+    pub fn pass(&mut self, name: &'static str) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            graph: self,
+            pass: Pass {
+                name,
+                uses: Vec::new(),
+                record: None,
+            },
+        }
+    }
+
+    /// Records the graph into `command_buffer`.
+    ///
+    /// The returned transient resources must be kept alive until the command
+    /// buffer is no longer in use.
+    pub unsafe fn execute(
+        mut self,
+        instance: &Instance,
+        device: &SharedDevice,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+    ) -> Transients {
+        let schedule = self.schedule();
+
+        let mut transients = Transients::new(
+            instance,
+            device,
+            data,
+            &self.resources,
+            &self.passes,
+            &schedule.order,
+        );
+
+        let mut description = self.describe(&schedule, &transients);
+        let handles = transients.handles(&self.resources);
+
+        let mut tracker = StateTracker::new();
+        for (index, resource) in self.resources.iter().enumerate() {
+            match resource {
+                Resource::Image { image, initial, .. } => tracker.import_image(*image, *initial),
+                Resource::Buffer {
+                    buffer, initial, ..
+                } => tracker.import_buffer(*buffer, *initial),
+                Resource::Transient { desc, .. } => {
+                    if let Some(image) = transients.image(ResourceId(index)) {
+                        // The memory may have been used by another image
+                        // before, which must be finished.
+                        let previous = Access {
+                            stage: vk::PipelineStageFlags2::ALL_COMMANDS,
+                            access: vk::AccessFlags2::MEMORY_WRITE,
+                            layout: vk::ImageLayout::UNDEFINED,
+                        };
+                        tracker.import_image_range(*image.image, range(desc.format), previous);
+                    }
+                }
+            }
+        }
+
+        let mut first_use = vec![true; self.resources.len()];
+        let breadcrumbs = self.breadcrumbs.zip(data.breadcrumbs.as_ref());
+        let timer = self.timestamps.zip(data.gpu_timer.as_ref());
+        for (position, index) in schedule.order.into_iter().enumerate() {
+            let pass = &mut self.passes[index];
+
+            for u in &pass.uses {
+                // Transient images have no contents before their first use.
+                let first = std::mem::replace(&mut first_use[u.resource.0], false);
+                let discard = u.discard
+                    || (first
+                        && matches!(self.resources[u.resource.0], Resource::Transient { .. }));
+
+                let image = match (&self.resources[u.resource.0], transients.image(u.resource)) {
+                    (Resource::Image { image, .. }, _) => *image,
+                    (_, Some(transient)) => *transient.image,
+                    (Resource::Buffer { buffer, .. }, _) => {
+                        tracker.use_buffer(*buffer, u.access);
+                        continue;
+                    }
+                    (Resource::Transient { .. }, None) => unreachable!(),
+                };
+
+                if discard {
+                    tracker.discard_image(image, u.access);
+                } else {
+                    tracker.use_image(image, u.access);
+                }
+            }
+
+            debug::begin_label(data, command_buffer, pass.name);
+            description.passes[index].barriers = describe_barriers(&tracker, &handles);
+            tracker.flush(device, data, command_buffer);
+
+            if let Some((slot, breadcrumbs)) = breadcrumbs {
+                breadcrumbs.mark(
+                    device,
+                    data,
+                    command_buffer,
+                    slot,
+                    position,
+                    Marker::Started,
+                );
+            }
+
+            let scope =
+                timer.map(|(slot, timer)| timer.begin(device, command_buffer, slot, pass.name));
+
+            if let Some(record) = pass.record.take() {
+                record(&PassContext {
+                    device,
+                    data,
+                    command_buffer,
+                    resources: &self.resources,
+                    transients: &transients,
+                });
+            }
+
+            if let Some((scope, (_, timer))) = scope.zip(timer) {
+                timer.end(device, command_buffer, scope);
+            }
+
+            if let Some((slot, breadcrumbs)) = breadcrumbs {
+                breadcrumbs.mark(
+                    device,
+                    data,
+                    command_buffer,
+                    slot,
+                    position,
+                    Marker::Finished,
+                );
+            }
+
+            debug::end_label(data, command_buffer);
+        }
+
+        for resource in &self.resources {
+            match resource {
+                Resource::Image {
+                    image,
+                    output: Some(access),
+                    ..
+                } => tracker.use_image(*image, *access),
+                Resource::Buffer {
+                    buffer,
+                    output: Some(access),
+                    ..
+                } => tracker.use_buffer(*buffer, *access),
+                _ => (),
+            }
+        }
+
+        description.final_barriers = describe_barriers(&tracker, &handles);
+        tracker.flush(device, data, command_buffer);
+
+        transients.description = description;
+        transients
+    }
+
+    fn describe(&self, schedule: &Schedule, transients: &Transients) -> GraphDescription {
+        let passes = self
+            .passes
+            .iter()
+            .enumerate()
+            .map(|(index, pass)| PassDescription {
+                name: pass.name,
+                queue: "graphics",
+                order: schedule.order.iter().position(|i| *i == index),
+                dependencies: schedule.dependencies[index].clone(),
+                needed_writes: schedule.needed_writes[index].clone(),
+                uses: pass
+                    .uses
+                    .iter()
+                    .map(|u| UseDescription {
+                        resource: u.resource.0,
+                        write: u.writes(),
+                        discard: u.discard,
+                        stage: format!("{:?}", u.access.stage),
+                        access: format!("{:?}", u.access.access),
+                        layout: format!("{:?}", u.access.layout),
+                    })
+                    .collect(),
+                barriers: Vec::new(),
+            })
+            .collect();
+
+        let resources = self
+            .resources
+            .iter()
+            .enumerate()
+            .map(|(index, resource)| ResourceDescription {
+                name: resource.name().to_owned(),
+                kind: match resource {
+                    Resource::Image { .. } => "imported image",
+                    Resource::Transient { .. } => "transient image",
+                    Resource::Buffer { .. } => "imported buffer",
+                },
+                output: resource
+                    .output()
+                    .map(|a| format!("{:?} {:?}", a.access, a.layout)),
+                memory_block: transients.image(ResourceId(index)).map(|i| i.block),
+            })
+            .collect();
+
+        GraphDescription {
+            passes,
+            resources,
+            final_barriers: Vec::new(),
+        }
+    }
+
+    fn schedule(&self) -> Schedule {
+        // Dependencies on earlier passes, by pass.
+        let dependencies = (0..self.passes.len())
+            .map(|index| {
+                let pass = &self.passes[index];
+                (0..index)
+                    .filter(|earlier| {
+                        let earlier = &self.passes[*earlier];
+                        pass.uses.iter().any(|u| {
+                            earlier
+                                .uses
+                                .iter()
+                                .any(|e| e.resource == u.resource && (e.writes() || u.writes()))
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // Walk back from the outputs to find the passes that contribute.
+        let mut live = vec![false; self.passes.len()];
+        let mut needed = self
+            .resources
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.output().is_some())
+            .map(|(i, _)| ResourceId(i))
+            .collect::<HashSet<_>>();
+
+        let mut needed_writes = vec![Vec::new(); self.passes.len()];
+        for index in (0..self.passes.len()).rev() {
+            let pass = &self.passes[index];
+            needed_writes[index] = pass
+                .uses
+                .iter()
+                .filter(|u| u.writes() && needed.contains(&u.resource))
+                .map(|u| u.resource.0)
+                .collect();
+
+            if !needed_writes[index].is_empty() {
+                live[index] = true;
+
+                // Earlier writes of overwritten resources are discarded.
+                for u in pass.uses.iter().filter(|u| u.discard) {
+                    needed.remove(&u.resource);
+                }
+                needed.extend(pass.uses.iter().filter(|u| !u.discard).map(|u| u.resource));
+            }
+        }
+
+        for (pass, live) in self.passes.iter().zip(&live) {
+            if !live {
+                tracing::debug!("culled pass {:?}", pass.name);
+            }
+        }
+
+        // Topological order, independent passes in declaration order.
+        let mut order = Vec::new();
+        let mut done = vec![false; self.passes.len()];
+        while let Some(next) = (0..self.passes.len()).find(|i| {
+            live[*i] && !done[*i] && dependencies[*i].iter().all(|d| done[*d] || !live[*d])
+        }) {
+            done[next] = true;
+            order.push(next);
+        }
+
+        Schedule {
+            order,
+            dependencies,
+            needed_writes,
+        }
+    }
+}
+
+struct Schedule {
+    /// Passes to execute in order.
+    order: Vec<usize>,
+    /// Earlier passes each pass depends on.
+    dependencies: Vec<Vec<usize>>,
+    /// Resources written by each pass that are needed by an output.
+    needed_writes: Vec<Vec<usize>>,
+}
+
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    pass: Pass<'a>,
+}
+
+impl<'g, 'a> PassBuilder<'g, 'a> {
+    /// Declares that the pass reads `resource` with `access`, which must not
+    /// include writes.
+    pub fn read(mut self, resource: ResourceId, access: Access) -> Self {
+        self.add(resource, access, false, false);
+        self
+    }
+
+    /// Declares that the pass writes `resource` with `access`, keeping its
+    /// previous contents.
+    pub fn write(mut self, resource: ResourceId, access: Access) -> Self {
+        self.add(resource, access, true, false);
+        self
+    }
+
+    /// Declares that the pass writes `resource` with `access` without needing
+    /// the previous contents, e.g. because it clears it.
+    pub fn overwrite(mut self, resource: ResourceId, access: Access) -> Self {
+        self.add(resource, access, true, true);
+        self
+    }
+
+    fn add(&mut self, resource: ResourceId, access: Access, write: bool, discard: bool) {
+        assert_eq!(
+            access.is_write(),
+            write,
+            "pass {:?} declares {:?} as {} with access {:?}",
+            self.pass.name,
+            self.graph.resources[resource.0].name(),
+            if write { "written" } else { "read" },
+            access.access
+        );
+
+        self.pass.uses.push(Use {
+            resource,
+            access,
+            discard,
+        });
+    }
+
+    /// Adds the pass to the graph. `record` is called when the graph is
+    /// executed, after the barriers for the pass.
+    pub fn execute(mut self, record: impl FnOnce(&PassContext) + 'a) {
+        self.pass.record = Some(Box::new(record));
+        self.graph.passes.push(self.pass);
+    }
+}
+
+/// Passed to the callback of a pass.
+pub struct PassContext<'r> {
+    pub device: &'r Device,
+    pub data: &'r AppData,
+    pub command_buffer: vk::CommandBuffer,
+    resources: &'r [Resource],
+    transients: &'r Transients,
+}
+
+impl PassContext<'_> {
+    pub fn image(&self, resource: ResourceId) -> vk::Image {
+        match (&self.resources[resource.0], self.transients.image(resource)) {
+            (Resource::Image { image, .. }, _) => *image,
+            (_, Some(transient)) => *transient.image,
+            _ => panic!("{:?} is not an image", self.resources[resource.0].name()),
+        }
+    }
+
+    pub fn view(&self, resource: ResourceId) -> vk::ImageView {
+        match (&self.resources[resource.0], self.transients.image(resource)) {
+            (Resource::Image { view, .. }, _) => *view,
+            (_, Some(transient)) => *transient.view,
+            _ => panic!("{:?} is not an image", self.resources[resource.0].name()),
+        }
+    }
+
+    pub fn extent(&self, resource: ResourceId) -> vk::Extent2D {
+        match &self.resources[resource.0] {
+            Resource::Image { extent, .. } => *extent,
+            Resource::Transient { desc, .. } => desc.extent,
+            Resource::Buffer { name, .. } => panic!("{:?} is not an image", name),
+        }
+    }
+
+    /// Returns the framebuffer created for a transient image, see
+    /// [`ImageDesc::render_pass`].
+    pub fn framebuffer(&self, resource: ResourceId) -> Option<vk::Framebuffer> {
+        self.transients
+            .image(resource)
+            .map(|i| *i.framebuffer)
+            .filter(|f| *f != vk::Framebuffer::null())
+    }
+
+    pub fn buffer(&self, resource: ResourceId) -> vk::Buffer {
+        match &self.resources[resource.0] {
+            Resource::Buffer { buffer, .. } => *buffer,
+            resource => panic!("{:?} is not a buffer", resource.name()),
+        }
+    }
+}
+
+struct Image {
+    /// Null if the image has no render pass.
+    framebuffer: Owned<vk::Framebuffer>,
+    view: Owned<vk::ImageView>,
+    image: Owned<vk::Image>,
+    /// Index of the memory block, shared by aliased images.
+    block: usize,
+}
+
+/// Transient images created for the execution of a graph.
+#[derive(Default)]
+pub struct Transients {
+    /// By resource index, `None` for imported and unused resources.
+    images: Vec<Option<Image>>,
+    /// Only kept to be freed, after the images bound to it.
+    #[allow(dead_code)]
+    memory: Vec<Owned<vk::DeviceMemory>>,
+    description: GraphDescription,
+}
+
+impl Transients {
+    unsafe fn new(
+        instance: &Instance,
+        device: &SharedDevice,
+        data: &AppData,
+        resources: &[Resource],
+        passes: &[Pass],
+        order: &[usize],
+    ) -> Self {
+        let lifetimes = lifetimes(resources.len(), passes, order);
+        let mut images = (0..resources.len()).map(|_| None).collect::<Vec<_>>();
+
+        let mut transients = resources
+            .iter()
+            .enumerate()
+            .filter_map(|(index, resource)| match (resource, lifetimes[index]) {
+                (Resource::Transient { name, desc }, Some(lifetime)) => {
+                    Some((index, name, desc, lifetime))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        transients.sort_by_key(|(_, _, _, (first, _))| *first);
+
+        let mut created = Vec::new();
+        for (index, name, desc, (first, last)) in transients {
+            let info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .extent(vk::Extent3D {
+                    width: desc.extent.width,
+                    height: desc.extent.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .format(desc.format)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .usage(desc.usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .samples(vk::SampleCountFlags::TYPE_1);
+
+            let image = Owned::new(device, device.create_image(&info, None).unwrap());
+            debug::set_object_name(device, data, *image, name);
+
+            let requirements = device.get_image_memory_requirements(*image);
+            created.push((index, image, requirements, (first, last)));
+        }
+
+        let blocks = assign_blocks(
+            &created
+                .iter()
+                .map(|(_, _, requirements, (first, last))| {
+                    (*first, *last, requirements.memory_type_bits)
+                })
+                .collect::<Vec<_>>(),
+        );
+        let block_count = blocks.iter().map(|b| b + 1).max().unwrap_or(0);
+
+        let mut memory = Vec::new();
+        for block_index in 0..block_count {
+            let members = created
+                .iter()
+                .zip(&blocks)
+                .filter(|(_, block)| **block == block_index)
+                .map(|(image, _)| image)
+                .collect::<Vec<_>>();
+            if members.len() > 1 {
+                tracing::debug!(
+                    "{} transient images share memory block {}",
+                    members.len(),
+                    block_index
+                );
+            }
+
+            // Images are bound at offset 0, the alignment is always met.
+            let size = members.iter().map(|(_, _, r, _)| r.size).max().unwrap();
+            let type_bits = members
+                .iter()
+                .fold(!0, |bits, (_, _, r, _)| bits & r.memory_type_bits);
+
+            let info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(size)
+                .memory_type_index(find_memory_type(
+                    instance,
+                    data,
+                    type_bits,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ));
+
+            let block_memory = Owned::new(device, device.allocate_memory(&info, None).unwrap());
+            debug::set_object_name(device, data, *block_memory, "transient image memory");
+            memory.push(block_memory);
+        }
+
+        for ((index, image, _, _), block) in created.into_iter().zip(blocks) {
+            device.bind_image_memory(*image, *memory[block], 0).unwrap();
+
+            let Resource::Transient { name, desc } = &resources[index] else {
+                unreachable!();
+            };
+
+            let info = vk::ImageViewCreateInfo::builder()
+                .image(*image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(desc.format)
+                .subresource_range(range(desc.format));
+
+            let view = Owned::new(device, device.create_image_view(&info, None).unwrap());
+            debug::set_object_name(device, data, *view, &format!("{} view", name));
+
+            let framebuffer = if desc.render_pass == vk::RenderPass::null() {
+                Owned::default()
+            } else {
+                let attachments = &[*view];
+                let info = vk::FramebufferCreateInfo::builder()
+                    .render_pass(desc.render_pass)
+                    .attachments(attachments)
+                    .width(desc.extent.width)
+                    .height(desc.extent.height)
+                    .layers(1);
+
+                let framebuffer =
+                    Owned::new(device, device.create_framebuffer(&info, None).unwrap());
+                debug::set_object_name(
+                    device,
+                    data,
+                    *framebuffer,
+                    &format!("{} framebuffer", name),
+                );
+                framebuffer
+            };
+
+            images[index] = Some(Image {
+                framebuffer,
+                view,
+                image,
+                block,
+            });
+        }
+
+        Self {
+            images,
+            memory,
+            description: GraphDescription::default(),
+        }
+    }
+
+    fn image(&self, resource: ResourceId) -> Option<&Image> {
+        self.images.get(resource.0).and_then(Option::as_ref)
+    }
+
+    /// Maps the raw handles of all resources to their index.
+    fn handles(&self, resources: &[Resource]) -> HashMap<u64, usize> {
+        resources
+            .iter()
+            .enumerate()
+            .filter_map(|(index, resource)| {
+                let handle = match (resource, self.image(ResourceId(index))) {
+                    (Resource::Image { image, .. }, _) => image.as_raw(),
+                    (_, Some(transient)) => transient.image.as_raw(),
+                    (Resource::Buffer { buffer, .. }, _) => buffer.as_raw(),
+                    (Resource::Transient { .. }, None) => return None,
+                };
+                Some((handle, index))
+            })
+            .collect()
+    }
+
+    /// Returns the structure of the graph, see [`crate::graph_export`].
+    pub fn description(&self) -> &GraphDescription {
+        &self.description
+    }
+}
+
+/// Returns the first and last position in `order` of every resource, `None`
+/// for resources not used by a scheduled pass.
+fn lifetimes(resources: usize, passes: &[Pass], order: &[usize]) -> Vec<Option<(usize, usize)>> {
+    let mut lifetimes = vec![None::<(usize, usize)>; resources];
+    for (position, index) in order.iter().enumerate() {
+        for u in &passes[*index].uses {
+            let lifetime = &mut lifetimes[u.resource.0];
+            *lifetime = Some(match lifetime {
+                Some((first, _)) => (*first, position),
+                None => (position, position),
+            });
+        }
+    }
+    lifetimes
+}
+
+/// Assigns a memory block to each image, given by its first and last position
+/// in the order and the memory types it supports, sorted by first position.
+/// Images share a block if their lifetimes don't overlap and they have a
+/// memory type in common.
+fn assign_blocks(images: &[(usize, usize, u32)]) -> Vec<usize> {
+    // Last position and common memory types of every block.
+    let mut blocks = Vec::<(usize, u32)>::new();
+
+    images
+        .iter()
+        .map(|(first, last, type_bits)| {
+            match blocks.iter_mut().position(|(block_last, block_bits)| {
+                *block_last < *first && *block_bits & type_bits != 0
+            }) {
+                Some(index) => {
+                    blocks[index].0 = *last;
+                    blocks[index].1 &= type_bits;
+                    index
+                }
+                None => {
+                    blocks.push((*last, *type_bits));
+                    blocks.len() - 1
+                }
+            }
+        })
+        .collect()
+}
+
+fn describe_barriers(
+    tracker: &StateTracker,
+    handles: &HashMap<u64, usize>,
+) -> Vec<BarrierDescription> {
+    let (buffers, images) = tracker.pending();
+
+    let buffers = buffers.iter().map(|b| BarrierDescription {
+        resource: handles[&b.buffer.as_raw()],
+        src_stage: format!("{:?}", b.src_stage),
+        src_access: format!("{:?}", b.src_access),
+        dst_stage: format!("{:?}", b.dst_stage),
+        dst_access: format!("{:?}", b.dst_access),
+        old_layout: None,
+        new_layout: None,
+    });
+
+    let images = images.iter().map(|b| BarrierDescription {
+        resource: handles[&b.image.as_raw()],
+        src_stage: format!("{:?}", b.src_stage),
+        src_access: format!("{:?}", b.src_access),
+        dst_stage: format!("{:?}", b.dst_stage),
+        dst_access: format!("{:?}", b.dst_access),
+        old_layout: Some(format!("{:?}", b.old_layout)),
+        new_layout: Some(format!("{:?}", b.new_layout)),
+    });
+
+    buffers.chain(images).collect()
+}
+
+fn range(format: vk::Format) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: aspect(format),
+        base_mip_level: 0,
+        level_count: 1,
+        base_array_layer: 0,
+        layer_count: 1,
+    }
+}
+
+fn aspect(format: vk::Format) -> vk::ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            vk::ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLED: Access = Access {
+        stage: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        access: vk::AccessFlags2::SHADER_SAMPLED_READ,
+        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    };
+
+    fn import(graph: &mut RenderGraph, name: &str) -> ResourceId {
+        let image = vk::Image::from_raw(graph.resources.len() as u64 + 1);
+        let extent = vk::Extent2D {
+            width: 1,
+            height: 1,
+        };
+        graph.import_image(
+            name,
+            image,
+            vk::ImageView::null(),
+            extent,
+            Access::UNDEFINED,
+        )
+    }
+
+    fn transient(graph: &mut RenderGraph, name: &str) -> ResourceId {
+        graph.create_image(
+            name,
+            ImageDesc {
+                format: vk::Format::R8G8B8A8_UNORM,
+                extent: vk::Extent2D {
+                    width: 1,
+                    height: 1,
+                },
+                usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                render_pass: vk::RenderPass::null(),
+            },
+        )
+    }
+
+    fn names(graph: &RenderGraph, order: &[usize]) -> Vec<&'static str> {
+        order.iter().map(|i| graph.passes[*i].name).collect()
+    }
+
+    #[test]
+    fn culls_passes_without_outputs() {
+        let mut graph = RenderGraph::new();
+        let target = import(&mut graph, "target");
+        let unused = transient(&mut graph, "unused");
+        graph.output(target, Access::PRESENT);
+
+        graph
+            .pass("unused")
+            .overwrite(unused, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+        graph
+            .pass("main")
+            .overwrite(target, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+
+        assert_eq!(names(&graph, &graph.schedule().order), ["main"]);
+    }
+
+    #[test]
+    fn culls_writes_discarded_by_an_overwrite() {
+        let mut graph = RenderGraph::new();
+        let target = import(&mut graph, "target");
+        graph.output(target, Access::PRESENT);
+
+        graph
+            .pass("discarded")
+            .overwrite(target, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+        graph
+            .pass("main")
+            .overwrite(target, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+
+        assert_eq!(names(&graph, &graph.schedule().order), ["main"]);
+    }
+
+    #[test]
+    fn keeps_writes_that_are_blended_onto() {
+        let mut graph = RenderGraph::new();
+        let target = import(&mut graph, "target");
+        graph.output(target, Access::PRESENT);
+
+        graph
+            .pass("clear")
+            .overwrite(target, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+        graph
+            .pass("blend")
+            .write(target, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+
+        assert_eq!(names(&graph, &graph.schedule().order), ["clear", "blend"]);
+    }
+
+    #[test]
+    fn orders_passes_after_their_dependencies() {
+        let mut graph = RenderGraph::new();
+        let target = import(&mut graph, "target");
+        let shadow = transient(&mut graph, "shadow");
+        let scene = transient(&mut graph, "scene");
+        graph.output(target, Access::PRESENT);
+
+        graph
+            .pass("shadow")
+            .overwrite(shadow, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+        graph
+            .pass("main")
+            .read(shadow, SAMPLED)
+            .overwrite(scene, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+        graph
+            .pass("post")
+            .read(scene, SAMPLED)
+            .overwrite(target, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+
+        let schedule = graph.schedule();
+        assert_eq!(names(&graph, &schedule.order), ["shadow", "main", "post"]);
+        assert_eq!(schedule.dependencies, [vec![], vec![0], vec![1]]);
+        assert_eq!(
+            schedule.needed_writes,
+            [vec![shadow.0], vec![scene.0], vec![target.0]]
+        );
+    }
+
+    #[test]
+    fn aliases_images_with_disjoint_lifetimes() {
+        let mut graph = RenderGraph::new();
+        let target = import(&mut graph, "target");
+        let first = transient(&mut graph, "first");
+        let second = transient(&mut graph, "second");
+        let third = transient(&mut graph, "third");
+        graph.output(target, Access::PRESENT);
+
+        graph
+            .pass("first")
+            .overwrite(first, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+        graph
+            .pass("second")
+            .read(first, SAMPLED)
+            .overwrite(second, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+        // `first` is no longer used, `third` can take its memory.
+        graph
+            .pass("third")
+            .read(second, SAMPLED)
+            .overwrite(third, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+        graph
+            .pass("final")
+            .read(third, SAMPLED)
+            .overwrite(target, Access::COLOR_ATTACHMENT)
+            .execute(|_| ());
+
+        let schedule = graph.schedule();
+        let lifetimes = lifetimes(graph.resources.len(), &graph.passes, &schedule.order);
+        assert_eq!(lifetimes[first.0], Some((0, 1)));
+        assert_eq!(lifetimes[second.0], Some((1, 2)));
+        assert_eq!(lifetimes[third.0], Some((2, 3)));
+
+        let images = [first, second, third]
+            .map(|r| lifetimes[r.0].unwrap())
+            .map(|(first, last)| (first, last, 1));
+        assert_eq!(assign_blocks(&images), [0, 1, 0]);
+    }
+
+    #[test]
+    fn does_not_alias_without_common_memory_type() {
+        assert_eq!(assign_blocks(&[(0, 0, 0b01), (1, 1, 0b10)]), [0, 1]);
+        assert_eq!(assign_blocks(&[(0, 0, 0b11), (1, 1, 0b10)]), [0, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "declares \"target\" as read")]
+    fn read_rejects_write_access() {
+        let mut graph = RenderGraph::new();
+        let target = import(&mut graph, "target");
+        graph.pass("main").read(target, Access::COLOR_ATTACHMENT);
+    }
+}
@@ -0,0 +1,360 @@
+//! Export of an executed render graph to Graphviz DOT and JSON.
+//!
+//! Set `HELLO_VULKAN_DUMP_GRAPH` to a path prefix to write
+//! `<prefix>.dot` and `<prefix>.json` when the frame graph is recorded, or
+//! press F11 to dump the graph of the current frame.
+
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+/// The structure of an executed render graph.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GraphDescription {
+    pub passes: Vec<PassDescription>,
+    pub resources: Vec<ResourceDescription>,
+    /// Barriers recorded after the last pass to leave the outputs in their
+    /// final state.
+    pub final_barriers: Vec<BarrierDescription>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PassDescription {
+    pub name: &'static str,
+    pub queue: &'static str,
+    /// Position in the execution order, `None` if the pass was culled.
+    pub order: Option<usize>,
+    /// Passes that must run before this pass, by index.
+    pub dependencies: Vec<usize>,
+    /// Resources written by the pass that are needed by an output, i.e. the
+    /// reason the pass was not culled.
+    pub needed_writes: Vec<usize>,
+    pub uses: Vec<UseDescription>,
+    /// Barriers recorded before the pass.
+    pub barriers: Vec<BarrierDescription>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UseDescription {
+    pub resource: usize,
+    pub write: bool,
+    pub discard: bool,
+    pub stage: String,
+    pub access: String,
+    pub layout: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ResourceDescription {
+    pub name: String,
+    pub kind: &'static str,
+    /// Final state of outputs.
+    pub output: Option<String>,
+    /// Memory block of transient images. Images in the same block alias.
+    pub memory_block: Option<usize>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BarrierDescription {
+    pub resource: usize,
+    pub src_stage: String,
+    pub src_access: String,
+    pub dst_stage: String,
+    pub dst_access: String,
+    /// `None` for buffers.
+    pub old_layout: Option<String>,
+    pub new_layout: Option<String>,
+}
+
+impl GraphDescription {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph frame {{").unwrap();
+        writeln!(dot, "    rankdir=LR;").unwrap();
+
+        for (index, pass) in self.passes.iter().enumerate() {
+            let mut label = match pass.order {
+                Some(order) => format!("#{} {}\\n[{}]", order, pass.name, pass.queue),
+                None => format!("{}\\n(culled)", pass.name),
+            };
+            for barrier in &pass.barriers {
+                write!(label, "\\n{}", self.barrier_label(barrier)).unwrap();
+            }
+
+            let style = if pass.order.is_some() {
+                "solid"
+            } else {
+                "dashed"
+            };
+            writeln!(
+                dot,
+                "    pass{} [shape=box, style={}, label=\"{}\"];",
+                index,
+                style,
+                escape(&label)
+            )
+            .unwrap();
+
+            for dependency in &pass.dependencies {
+                writeln!(
+                    dot,
+                    "    pass{} -> pass{} [style=dotted, color=gray];",
+                    dependency, index
+                )
+                .unwrap();
+            }
+
+            for u in &pass.uses {
+                let label = format!("{}\\n{}", u.access, u.layout);
+                let (from, to) = if u.write {
+                    (format!("pass{}", index), format!("res{}", u.resource))
+                } else {
+                    (format!("res{}", u.resource), format!("pass{}", index))
+                };
+
+                let color = if pass.needed_writes.contains(&u.resource) {
+                    "black"
+                } else {
+                    "gray"
+                };
+                writeln!(
+                    dot,
+                    "    {} -> {} [color={}, label=\"{}\"];",
+                    from,
+                    to,
+                    color,
+                    escape(&label)
+                )
+                .unwrap();
+            }
+        }
+
+        for (index, resource) in self.resources.iter().enumerate() {
+            let mut label = format!("{}\\n{}", resource.name, resource.kind);
+            if let Some(block) = resource.memory_block {
+                write!(label, "\\nmemory block {}", block).unwrap();
+            }
+            if let Some(output) = &resource.output {
+                write!(label, "\\noutput: {}", output).unwrap();
+            }
+
+            let shape = if resource.output.is_some() {
+                "doubleoctagon"
+            } else {
+                "ellipse"
+            };
+            writeln!(
+                dot,
+                "    res{} [shape={}, label=\"{}\"];",
+                index,
+                shape,
+                escape(&label)
+            )
+            .unwrap();
+        }
+
+        // Transient images sharing memory.
+        for (a, first) in self.resources.iter().enumerate() {
+            for (b, second) in self.resources.iter().enumerate().skip(a + 1) {
+                if first.memory_block.is_some() && first.memory_block == second.memory_block {
+                    writeln!(
+                        dot,
+                        "    res{} -> res{} [dir=none, style=dashed, color=red, label=\"aliased\"];",
+                        a, b
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+
+    fn barrier_label(&self, barrier: &BarrierDescription) -> String {
+        let mut label = format!(
+            "barrier {}: {}/{} -> {}/{}",
+            self.resources[barrier.resource].name,
+            barrier.src_stage,
+            barrier.src_access,
+            barrier.dst_stage,
+            barrier.dst_access
+        );
+        if let (Some(old), Some(new)) = (&barrier.old_layout, &barrier.new_layout) {
+            if old != new {
+                write!(label, " {} -> {}", old, new).unwrap();
+            }
+        }
+
+        label
+    }
+
+    /// Writes `<prefix>.dot` and `<prefix>.json`.
+    pub fn write(&self, prefix: &Path) -> io::Result<()> {
+        std::fs::write(with_suffix(prefix, ".dot"), self.to_dot())?;
+        std::fs::write(with_suffix(prefix, ".json"), self.to_json())?;
+
+        tracing::info!("wrote render graph to {}.{{dot,json}}", prefix.display());
+        Ok(())
+    }
+}
+
+/// Writes `description` if `HELLO_VULKAN_DUMP_GRAPH` is set.
+pub fn dump_if_requested(description: &GraphDescription) {
+    if let Some(prefix) = std::env::var_os("HELLO_VULKAN_DUMP_GRAPH") {
+        if let Err(err) = description.write(Path::new(&prefix)) {
+            tracing::error!("failed to write render graph: {}", err);
+        }
+    }
+}
+
+/// Appends `suffix` to the file name of `prefix`, which may contain dots.
+fn with_suffix(prefix: &Path, suffix: &str) -> PathBuf {
+    let mut path = prefix.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn escape(label: &str) -> String {
+    label.replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clear pass writing the output, and a culled pass reading a transient
+    /// image it writes.
+    fn graph() -> GraphDescription {
+        let color_use = |resource, write| UseDescription {
+            resource,
+            write,
+            discard: write,
+            stage: "COLOR_ATTACHMENT_OUTPUT".to_owned(),
+            access: "COLOR_ATTACHMENT_WRITE".to_owned(),
+            layout: "COLOR_ATTACHMENT_OPTIMAL".to_owned(),
+        };
+
+        GraphDescription {
+            passes: vec![
+                PassDescription {
+                    name: "clear",
+                    queue: "graphics",
+                    order: Some(0),
+                    dependencies: vec![],
+                    needed_writes: vec![0],
+                    uses: vec![color_use(0, true)],
+                    barriers: vec![BarrierDescription {
+                        resource: 0,
+                        src_stage: "NONE".to_owned(),
+                        src_access: "NONE".to_owned(),
+                        dst_stage: "COLOR_ATTACHMENT_OUTPUT".to_owned(),
+                        dst_access: "COLOR_ATTACHMENT_WRITE".to_owned(),
+                        old_layout: Some("UNDEFINED".to_owned()),
+                        new_layout: Some("COLOR_ATTACHMENT_OPTIMAL".to_owned()),
+                    }],
+                },
+                PassDescription {
+                    name: "unused",
+                    queue: "graphics",
+                    order: None,
+                    dependencies: vec![0],
+                    needed_writes: vec![],
+                    uses: vec![color_use(0, false), color_use(1, true)],
+                    barriers: vec![],
+                },
+            ],
+            resources: vec![
+                ResourceDescription {
+                    name: "swapchain".to_owned(),
+                    kind: "image",
+                    output: Some("PRESENT_SRC_KHR".to_owned()),
+                    memory_block: None,
+                },
+                ResourceDescription {
+                    name: "scratch".to_owned(),
+                    kind: "transient image",
+                    output: None,
+                    memory_block: Some(0),
+                },
+            ],
+            final_barriers: vec![],
+        }
+    }
+
+    #[test]
+    fn dot() {
+        let dot = graph().to_dot();
+        let lines = dot.lines().map(str::trim).collect::<Vec<_>>();
+        let line = |prefix: &str| {
+            lines
+                .iter()
+                .find(|l| l.starts_with(prefix))
+                .unwrap_or_else(|| panic!("no {:?} in\n{}", prefix, dot))
+                .to_string()
+        };
+
+        assert_eq!(lines.first(), Some(&"digraph frame {"));
+        assert_eq!(lines.last(), Some(&"}"));
+
+        // Nodes.
+        let clear = line("pass0 [");
+        assert!(clear.contains("style=solid"), "{}", clear);
+        assert!(clear.contains("#0 clear\\n[graphics]"), "{}", clear);
+        assert!(
+            clear.contains("barrier swapchain: NONE/NONE -> COLOR_ATTACHMENT_OUTPUT/COLOR_ATTACHMENT_WRITE UNDEFINED -> COLOR_ATTACHMENT_OPTIMAL"),
+            "{}",
+            clear
+        );
+        let culled = line("pass1 [");
+        assert!(culled.contains("style=dashed"), "{}", culled);
+        assert!(culled.contains("unused\\n(culled)"), "{}", culled);
+        assert!(line("res0 [").contains("shape=doubleoctagon"));
+        assert!(line("res1 [").contains("memory block 0"));
+
+        // Edges: needed writes are black, other uses and culled passes gray.
+        assert!(line("pass0 -> res0 ").contains("color=black"));
+        assert!(line("res0 -> pass1 ").contains("color=gray"));
+        assert!(line("pass1 -> res1 ").contains("color=gray"));
+        assert!(line("pass0 -> pass1 ").contains("style=dotted"));
+    }
+
+    #[test]
+    fn json() {
+        let json: serde_json::Value = serde_json::from_str(&graph().to_json()).unwrap();
+
+        let passes = json["passes"].as_array().unwrap();
+        assert_eq!(passes.len(), 2);
+        assert_eq!(passes[0]["name"], "clear");
+        assert_eq!(passes[0]["order"], 0);
+        assert_eq!(
+            passes[0]["barriers"][0]["new_layout"],
+            "COLOR_ATTACHMENT_OPTIMAL"
+        );
+        assert_eq!(passes[1]["order"], serde_json::Value::Null);
+        assert_eq!(passes[1]["dependencies"], serde_json::json!([0]));
+        assert_eq!(passes[1]["uses"][1]["resource"], 1);
+        assert_eq!(passes[1]["uses"][1]["write"], true);
+
+        assert_eq!(json["resources"][0]["output"], "PRESENT_SRC_KHR");
+        assert_eq!(json["resources"][1]["memory_block"], 0);
+    }
+
+    #[test]
+    fn suffix_is_appended_to_the_prefix() {
+        assert_eq!(
+            with_suffix(Path::new("out/frame.v2"), ".dot"),
+            Path::new("out/frame.v2.dot")
+        );
+        assert_eq!(
+            with_suffix(Path::new("frame"), ".json"),
+            Path::new("frame.json")
+        );
+    }
+}
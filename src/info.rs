@@ -0,0 +1,505 @@
+//! `info [--json] [--surface]`
+//!
+//! Reports the Vulkan capabilities of the machine without creating a device:
+//! the instance layers and extensions, and for every physical device its
+//! properties, limits, features, memory, queue families and the ones
+//! [`queues`](crate::queues) would select, extensions and format support, with the problems found by the checks of
+//! [`pick_physical_device`](crate::pick_physical_device) and the device that
+//! would be selected.
+//!
+//! Without a window the checks are those of offscreen rendering and the
+//! device is reported as `selected_offscreen_device`: the window can only use
+//! a device that presents to its surface. `--surface` creates an invisible
+//! window to apply the checks of the app, reporting `selected_device` and the
+//! surface capabilities as well. `--json` prints the report as JSON instead of
+//! text.
+
+use std::fmt::Write as _;
+use std::rc::Rc;
+
+use ash::vk;
+use ash::{Entry, Instance};
+use serde_json::{json, Map, Value};
+use winit::event_loop::EventLoop;
+use winit::window::{Window, WindowBuilder};
+
+use crate::config::Config;
+use crate::{
+    create_surface, get_required_instance_extensions, is_configured_device,
+    physical_device_problems, read_cstr, AppData, SwapchainSupport, FEATURES, VALIDATION_LAYER,
+};
+use crate::{features, owned, present, queues, surface_format};
+
+/// Formats whose support is reported: swapchain candidates, render targets,
+/// depth buffers and compressed textures.
+const FORMATS: &[vk::Format] = &[
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::B8G8R8A8_UNORM,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::A2B10G10R10_UNORM_PACK32,
+    vk::Format::A2R10G10B10_UNORM_PACK32,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R32G32B32A32_SFLOAT,
+    vk::Format::D16_UNORM,
+    vk::Format::D32_SFLOAT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::BC7_SRGB_BLOCK,
+    vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+    vk::Format::ASTC_4X4_SRGB_BLOCK,
+];
+
+pub fn run(config: &Config, args: &[String]) {
+    let mut json = false;
+    let mut surface = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--surface" => surface = true,
+            _ => panic!("unknown argument {} for info", arg),
+        }
+    }
+
+    // The window determines the surface extensions of the instance.
+    let event_loop = surface.then(EventLoop::new);
+    let window = event_loop.as_ref().map(|event_loop| {
+        WindowBuilder::new()
+            .with_title(&config.app_name)
+            .with_visible(false)
+            .build(event_loop)
+            .unwrap()
+    });
+
+    let entry = unsafe { Entry::load() }.unwrap_or_else(|err| {
+        eprintln!("failed to load the Vulkan loader: {}", err);
+        std::process::exit(1);
+    });
+    let report = unsafe { report(entry, config, window.as_ref()) };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        let mut out = String::new();
+        format_text(&report, 0, &mut out);
+        print!("{}", out);
+    }
+}
+
+unsafe fn report(entry: Entry, config: &Config, window: Option<&Window>) -> Value {
+    let instance_version = features::instance_version(&entry);
+    let loader_version = entry
+        .try_enumerate_instance_version()
+        .unwrap()
+        .unwrap_or(vk::API_VERSION_1_0);
+
+    let layers = entry.enumerate_instance_layer_properties().unwrap();
+    let extensions = entry.enumerate_instance_extension_properties(None).unwrap();
+
+    // Only the surface extensions are enabled, if all are available.
+    let (required, mut missing) = match window.map(|w| get_required_instance_extensions(w)) {
+        Some(Ok(required)) => (required, vec![]),
+        Some(Err(err)) => (vec![], vec![err]),
+        None => (vec![], vec![]),
+    };
+    missing.extend(
+        required
+            .iter()
+            .filter(|name| {
+                !extensions
+                    .iter()
+                    .any(|e| read_cstr(&e.extension_name) == **name)
+            })
+            .map(|name| name.to_string_lossy().into_owned()),
+    );
+    let enabled = if missing.is_empty() {
+        required.iter().map(|e| e.as_ptr()).collect()
+    } else {
+        vec![]
+    };
+
+    let app_info = vk::ApplicationInfo::builder().api_version(instance_version);
+    let info = vk::InstanceCreateInfo::builder()
+        .application_info(&app_info)
+        .enabled_extension_names(&enabled);
+    let instance = entry.create_instance(&info, None).unwrap();
+    let instance = owned::InstanceOwner::new(entry, instance, None, Rc::default());
+    let entry = instance.entry();
+
+    let mut data = AppData {
+        config: config.clone(),
+        headless: true,
+        instance_version,
+        ..Default::default()
+    };
+    if let (Some(window), true) = (window, missing.is_empty()) {
+        data.headless = false;
+        data.surface = owned::Surface::new(&instance, create_surface(entry, &instance, window));
+    }
+
+    let mut selected = None;
+    let devices = instance
+        .enumerate_physical_devices()
+        .unwrap()
+        .into_iter()
+        .enumerate()
+        .map(|(index, physical_device)| {
+            let properties = instance.get_physical_device_properties(physical_device);
+            let name = read_cstr(&properties.device_name);
+
+            let mut problems = physical_device_problems(entry, &instance, &data, physical_device);
+            if !is_configured_device(&data, index, name) {
+                problems.insert(0, "not selected by configuration".to_owned());
+            }
+
+            let name = name.to_string_lossy().into_owned();
+            if problems.is_empty() && selected.is_none() {
+                selected = Some(name.clone());
+            }
+
+            let mut device = device_report(entry, &instance, &data, physical_device);
+            device.insert("index".to_owned(), json!(index));
+            device.insert("name".to_owned(), json!(name));
+            device.insert("problems".to_owned(), json!(problems));
+            Value::Object(device)
+        })
+        .collect::<Vec<_>>();
+
+    // Without a surface, present support is not checked and the window may
+    // end up on a different device.
+    let selected_key = if data.headless {
+        "selected_offscreen_device"
+    } else {
+        "selected_device"
+    };
+
+    let mut report = json!({
+        "instance": {
+            "loader_version": features::format_version(loader_version),
+            "api_version": features::format_version(instance_version),
+            "validation_layer": layers
+                .iter()
+                .any(|l| read_cstr(&l.layer_name) == VALIDATION_LAYER),
+            "layers": layers
+                .iter()
+                .map(|l| format!(
+                    "{} {}",
+                    read_cstr(&l.layer_name).to_string_lossy(),
+                    features::format_version(l.spec_version)
+                ))
+                .collect::<Vec<_>>(),
+            "extensions": extensions
+                .iter()
+                .map(|e| read_cstr(&e.extension_name).to_string_lossy())
+                .collect::<Vec<_>>(),
+            "missing_surface_extensions": missing,
+        },
+        "checks": if data.headless { "offscreen" } else { "window" },
+        "devices": devices,
+    });
+    report[selected_key] = json!(selected);
+    report
+}
+
+unsafe fn device_report(
+    entry: &Entry,
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Map<String, Value> {
+    let properties = instance.get_physical_device_properties(physical_device);
+    let supported =
+        features::DeviceFeatures::query(instance, data.instance_version, physical_device);
+    let memory = instance.get_physical_device_memory_properties(physical_device);
+    let queue_families = instance.get_physical_device_queue_family_properties(physical_device);
+    let surface_ext = ash::extensions::khr::Surface::new(entry, instance);
+
+    let limits = properties.limits;
+    let mut limit_values = Map::new();
+    macro_rules! limits {
+        ($($field:ident),* $(,)?) => {
+            $(limit_values.insert(stringify!($field).to_owned(), json!(limits.$field));)*
+        };
+    }
+    limits!(
+        max_image_dimension1_d,
+        max_image_dimension2_d,
+        max_image_dimension3_d,
+        max_image_dimension_cube,
+        max_image_array_layers,
+        max_texel_buffer_elements,
+        max_uniform_buffer_range,
+        max_storage_buffer_range,
+        max_push_constants_size,
+        max_memory_allocation_count,
+        max_sampler_allocation_count,
+        buffer_image_granularity,
+        max_bound_descriptor_sets,
+        max_per_stage_descriptor_samplers,
+        max_per_stage_descriptor_uniform_buffers,
+        max_per_stage_descriptor_storage_buffers,
+        max_per_stage_descriptor_sampled_images,
+        max_per_stage_resources,
+        max_vertex_input_attributes,
+        max_fragment_output_attachments,
+        max_compute_shared_memory_size,
+        max_compute_work_group_count,
+        max_compute_work_group_invocations,
+        max_compute_work_group_size,
+        max_sampler_anisotropy,
+        max_viewports,
+        max_viewport_dimensions,
+        max_framebuffer_width,
+        max_framebuffer_height,
+        max_color_attachments,
+        timestamp_compute_and_graphics,
+        timestamp_period,
+        min_uniform_buffer_offset_alignment,
+        min_storage_buffer_offset_alignment,
+        optimal_buffer_copy_offset_alignment,
+        non_coherent_atom_size,
+    );
+
+    let core = supported.features;
+    let mut core_features = Map::new();
+    macro_rules! core_features {
+        ($($field:ident),* $(,)?) => {
+            $(core_features.insert(stringify!($field).to_owned(), json!(core.$field == vk::TRUE));)*
+        };
+    }
+    core_features!(
+        robust_buffer_access,
+        geometry_shader,
+        tessellation_shader,
+        sample_rate_shading,
+        multi_draw_indirect,
+        fill_mode_non_solid,
+        wide_lines,
+        sampler_anisotropy,
+        texture_compression_bc,
+        texture_compression_etc2,
+        texture_compression_astc_ldr,
+        pipeline_statistics_query,
+        vertex_pipeline_stores_and_atomics,
+        fragment_stores_and_atomics,
+        shader_float64,
+        shader_int64,
+        shader_int16,
+    );
+
+    let app_features = |list: &[features::Feature]| {
+        list.iter()
+            .map(|f| (format!("{:?}", f), json!(supported.is_enabled(*f))))
+            .collect::<Map<_, _>>()
+    };
+
+    let mut formats = Map::new();
+    for format in FORMATS {
+        let properties = instance.get_physical_device_format_properties(physical_device, *format);
+        formats.insert(
+            format!("{:?}", format),
+            json!({
+                "optimal_tiling": format!("{:?}", properties.optimal_tiling_features),
+                "linear_tiling": format!("{:?}", properties.linear_tiling_features),
+                "buffer": format!("{:?}", properties.buffer_features),
+            }),
+        );
+    }
+
+    let extensions = instance
+        .enumerate_device_extension_properties(physical_device)
+        .unwrap()
+        .iter()
+        .map(|e| read_cstr(&e.extension_name).to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+
+    let mut report = Map::new();
+    report.insert(
+        "properties".to_owned(),
+        json!({
+            "type": format!("{:?}", properties.device_type),
+            "api_version": features::format_version(properties.api_version),
+            "driver_version": properties.driver_version,
+            "vendor_id": format!("{:#06x}", properties.vendor_id),
+            "device_id": format!("{:#06x}", properties.device_id),
+        }),
+    );
+    report.insert("limits".to_owned(), Value::Object(limit_values));
+    report.insert(
+        "features".to_owned(),
+        json!({
+            "required": app_features(FEATURES.required),
+            "optional": app_features(FEATURES.optional),
+            "core": core_features,
+        }),
+    );
+    report.insert(
+        "memory_heaps".to_owned(),
+        memory.memory_heaps[..memory.memory_heap_count as usize]
+            .iter()
+            .map(|heap| {
+                json!({
+                    "size": format!("{:.1} GiB", heap.size as f64 / (1u64 << 30) as f64),
+                    "flags": format!("{:?}", heap.flags),
+                })
+            })
+            .collect(),
+    );
+    report.insert(
+        "memory_types".to_owned(),
+        memory.memory_types[..memory.memory_type_count as usize]
+            .iter()
+            .map(|t| json!({ "heap": t.heap_index, "flags": format!("{:?}", t.property_flags) }))
+            .collect(),
+    );
+    report.insert(
+        "queue_families".to_owned(),
+        queue_families
+            .iter()
+            .enumerate()
+            .map(|(index, family)| {
+                let granularity = family.min_image_transfer_granularity;
+                let mut family = json!({
+                    "index": index,
+                    "flags": format!("{:?}", family.queue_flags),
+                    "count": family.queue_count,
+                    "timestamp_valid_bits": family.timestamp_valid_bits,
+                    "min_image_transfer_granularity":
+                        [granularity.width, granularity.height, granularity.depth],
+                });
+                if !data.headless {
+                    family["present"] = json!(surface_ext
+                        .get_physical_device_surface_support(
+                            physical_device,
+                            index as u32,
+                            *data.surface
+                        )
+                        .unwrap());
+                }
+                family
+            })
+            .collect(),
+    );
+    if let Some(families) = queues::QueueFamilies::get(entry, instance, data, physical_device) {
+        report.insert(
+            "selected_queue_families".to_owned(),
+            json!({
+                "graphics": families.graphics,
+                "present": families.present,
+                "compute": families.compute,
+                "transfer": families.transfer,
+            }),
+        );
+    }
+    report.insert("extensions".to_owned(), json!(extensions));
+    report.insert("formats".to_owned(), Value::Object(formats));
+
+    if !data.headless {
+        report.insert(
+            "surface".to_owned(),
+            surface_report(entry, instance, data, physical_device),
+        );
+    }
+
+    report
+}
+
+unsafe fn surface_report(
+    entry: &Entry,
+    instance: &Instance,
+    data: &AppData,
+    physical_device: vk::PhysicalDevice,
+) -> Value {
+    let support = SwapchainSupport::get(entry, instance, data, physical_device);
+    let capabilities = support.capabilities;
+    let extent = |e: vk::Extent2D| [e.width, e.height];
+
+    let (format, present_mode) = if support.formats.is_empty() || support.present_modes.is_empty() {
+        (Value::Null, Value::Null)
+    } else {
+        let format = surface_format::select(data.config.surface_format, &support.formats);
+        let present_mode = present::select(&data.config.present_modes, &support.present_modes);
+        (
+            json!(format!("{:?} {:?}", format.format, format.color_space)),
+            json!(format!("{:?}", present_mode)),
+        )
+    };
+
+    json!({
+        "min_image_count": capabilities.min_image_count,
+        "max_image_count": capabilities.max_image_count,
+        "current_extent": extent(capabilities.current_extent),
+        "min_image_extent": extent(capabilities.min_image_extent),
+        "max_image_extent": extent(capabilities.max_image_extent),
+        "supported_usage": format!("{:?}", capabilities.supported_usage_flags),
+        "supported_transforms": format!("{:?}", capabilities.supported_transforms),
+        "supported_composite_alpha": format!("{:?}", capabilities.supported_composite_alpha),
+        "formats": support
+            .formats
+            .iter()
+            .map(|f| format!("{:?} {:?}", f.format, f.color_space))
+            .collect::<Vec<_>>(),
+        "present_modes": support
+            .present_modes
+            .iter()
+            .map(|m| format!("{:?}", m))
+            .collect::<Vec<_>>(),
+        "selected_format": format,
+        "selected_present_mode": present_mode,
+    })
+}
+
+/// Formats `value` as indented text: objects as `key: value` lines, arrays of
+/// objects as items headed by their name or index.
+fn format_text(value: &Value, indent: usize, out: &mut String) {
+    let pad = " ".repeat(indent);
+
+    let scalar = |value: &Value| match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "none".to_owned(),
+        Value::Array(items) if items.is_empty() => "none".to_owned(),
+        Value::Array(items) => items
+            .iter()
+            .map(|i| match i {
+                Value::String(s) => s.clone(),
+                i => i.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        value => value.to_string(),
+    };
+    let is_scalar = |value: &Value| match value {
+        Value::Object(_) => false,
+        Value::Array(items) => !items.iter().any(|i| i.is_object() || i.is_array()),
+        _ => true,
+    };
+
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                if is_scalar(value) {
+                    writeln!(out, "{}{}: {}", pad, key, scalar(value)).unwrap();
+                } else {
+                    writeln!(out, "{}{}:", pad, key).unwrap();
+                    format_text(value, indent + 2, out);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let name = item
+                    .get("name")
+                    .map(scalar)
+                    .unwrap_or_else(|| i.to_string());
+                writeln!(out, "{}- {}", pad, name).unwrap();
+
+                let mut item = item.clone();
+                if let Some(map) = item.as_object_mut() {
+                    map.remove("name");
+                }
+                format_text(&item, indent + 2, out);
+            }
+        }
+        value => writeln!(out, "{}{}", pad, scalar(value)).unwrap(),
+    }
+}
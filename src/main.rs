@@ -1,13 +1,41 @@
-use std::collections::HashSet;
-use std::ffi::{c_char, c_void, CStr};
+mod barrier;
+mod breadcrumbs;
+mod config;
+mod debug;
+mod deletion;
+mod features;
+mod graph;
+mod graph_export;
+mod info;
+mod memory;
+mod offscreen;
+mod owned;
+mod pacing;
+mod present;
+mod queues;
+mod registry;
+mod rendering;
+mod scene;
+mod screenshot;
+mod state;
+mod surface_format;
+mod timeline;
+mod timing;
+mod upload;
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use ash::extensions::ext::DebugUtils;
 use ash::extensions::khr::{WaylandSurface, Win32Surface, XcbSurface, XlibSurface};
+use ash::prelude::VkResult;
 use ash::vk::{
-    self, make_version, ApplicationInfo, Bool32, DebugUtilsMessageSeverityFlagsEXT,
-    DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCallbackDataEXT,
-    DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT, DeviceQueueCreateInfo,
-    InstanceCreateFlags, InstanceCreateInfo, SurfaceKHR, SwapchainKHR,
+    self, make_version, ApplicationInfo, DebugUtilsMessageSeverityFlagsEXT,
+    DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCreateInfoEXT, DeviceQueueCreateInfo,
+    InstanceCreateFlags, InstanceCreateInfo, SwapchainKHR,
 };
 use ash::Device;
 use ash::Entry;
@@ -16,156 +44,578 @@ use raw_window_handle::{
     HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle,
 };
 use winit::dpi::LogicalSize;
-use winit::event::{Event, WindowEvent};
-use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Window, WindowBuilder};
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop, EventLoopWindowTarget};
+use winit::window::{Window, WindowBuilder, WindowId};
+
+use owned::{Owned, SharedDevice, SharedInstance};
 
 fn main() {
     pretty_env_logger::init();
 
+    let args = std::env::args().collect::<Vec<_>>();
+    let (config, args) = config::Config::load(&args).unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        std::process::exit(2);
+    });
+    match args.first().map(String::as_str) {
+        Some("render") => return render_headless(&config, &args[1..]),
+        Some("info") => return info::run(&config, &args[1..]),
+        Some(command) => panic!("unknown command {}", command),
+        None => (),
+    }
+
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
-        .with_inner_size(LogicalSize::new(800, 600))
+        .with_title(&config.app_name)
+        .with_inner_size(LogicalSize::new(config.width, config.height))
         .build(&event_loop)
         .unwrap();
 
-    let mut app = unsafe { App::create(&window) };
-    let mut destroying = false;
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+    // `None` once the app is destroyed. The event loop never returns, so the
+    // app must be dropped explicitly.
+    let mut app = Some(unsafe { App::create(Rc::new(window), &config) });
+    event_loop.run(move |event, target, control_flow| {
+        let Some(current) = &mut app else {
+            return;
+        };
+        let debug_state = current.renderer.data.debug_state.clone();
+
         match event {
-            // Render a frame if our Vulkan app is not being destroyed.
-            Event::MainEventsCleared if !destroying => unsafe { app.render(&window) },
-            // Destroy our Vulkan app.
-            Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
-                ..
-            } => {
-                destroying = true;
-                *control_flow = ControlFlow::Exit;
-
-                unsafe {
-                    app.device.device_wait_idle().unwrap();
-                    app.destroy();
+            // Schedule the next frame of every window.
+            Event::MainEventsCleared => *control_flow = current.update(),
+            // Render a frame.
+            Event::RedrawRequested(window_id) => match unsafe { current.render(window_id) } {
+                Ok(()) => (),
+                Err(vk::Result::ERROR_DEVICE_LOST) => {
+                    unsafe { current.report_device_lost() };
+
+                    if config.device_lost == breadcrumbs::DeviceLostMode::Panic {
+                        panic!("device lost");
+                    }
+
+                    tracing::warn!("recreating the device");
+                    let lost = app.take().unwrap();
+                    app = Some(unsafe { lost.recreate_device() });
+                }
+                Err(err) => panic!("failed to render frame: {}", err),
+            },
+            Event::WindowEvent { window_id, event } => {
+                if let Some(window) = current.windows.get_mut(&window_id) {
+                    window.pacer.window_event(&window.window, &event);
+                }
+
+                match event {
+                    WindowEvent::CloseRequested => {
+                        current.close_window(window_id);
+
+                        // Destroy our Vulkan app with the last window.
+                        if current.windows.is_empty() {
+                            *control_flow = ControlFlow::Exit;
+
+                            app = None;
+
+                            if debug_state.failed() {
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    } => unsafe { current.key_pressed(window_id, key, target) },
+                    _ => {}
                 }
             }
             _ => {}
         }
+
+        // Raises validation errors of calls not checked more closely, e.g.
+        // when objects are destroyed.
+        debug_state.check();
     });
 }
 
+/// `render <output.png> [<width> <height>]`
+///
+/// Renders a single frame offscreen without opening a window and writes it to
+/// `output.png`. The size defaults to the configured window size. Used by the
+/// golden image tests.
+fn render_headless(config: &config::Config, args: &[String]) {
+    let output = args.first().expect("missing output path");
+    let width = args
+        .get(1)
+        .map(|w| w.parse().unwrap())
+        .unwrap_or(config.width);
+    let height = args
+        .get(2)
+        .map(|h| h.parse().unwrap())
+        .unwrap_or(config.height);
+
+    unsafe {
+        let renderer = Renderer::create_headless(config, vk::Extent2D { width, height });
+        let screenshot = renderer.render_offscreen();
+        renderer.data.debug_state.check();
+
+        let debug_state = renderer.data.debug_state.clone();
+        drop(renderer);
+
+        screenshot::write_png(Path::new(output), &screenshot).unwrap();
+
+        if debug_state.failed() {
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The windows and the renderer they share.
+///
+/// Windows are opened with N and closed at runtime, each with its own surface,
+/// swapchain and frame pacing, see [`View`].
 struct App {
-    entry: Entry,
-    instance: Instance,
-    data: AppData,
-    device: Device,
+    // Dropped before the renderer whose device they use.
+    windows: HashMap<WindowId, AppWindow>,
+    renderer: Renderer,
+}
+
+struct AppWindow {
+    view: View,
+    pacer: pacing::FramePacer,
+    /// Also kept by the view until its surface is destroyed, which must not
+    /// outlive the window.
+    window: Rc<Window>,
 }
 
 impl App {
-    unsafe fn create(window: &Window) -> Self {
-        let mut data = AppData::default();
-
-        let entry = Entry::load().unwrap();
-        let instance = create_instance(window, &entry, &mut data);
-
-        let surface = match (window.raw_display_handle(), window.raw_window_handle()) {
-            (RawDisplayHandle::Xcb(display), RawWindowHandle::Xcb(window)) => {
-                let info = vk::XcbSurfaceCreateInfoKHR::builder()
-                    .window(window.window)
-                    .connection(display.connection)
-                    .build();
-
-                XcbSurface::new(&entry, &instance)
-                    .create_xcb_surface(&info, None)
-                    .unwrap()
-            }
-            (RawDisplayHandle::Xlib(display), RawWindowHandle::Xlib(window)) => {
-                let info = vk::XlibSurfaceCreateInfoKHR::builder()
-                    .window(window.window)
-                    .dpy(display.display as *mut _)
-                    .build();
-
-                XlibSurface::new(&entry, &instance)
-                    .create_xlib_surface(&info, None)
-                    .unwrap()
-            }
-            (RawDisplayHandle::Windows(_), RawWindowHandle::Win32(window)) => {
-                let info = vk::Win32SurfaceCreateInfoKHR::builder()
-                    .hinstance(window.hinstance)
-                    .hwnd(window.hwnd);
-
-                Win32Surface::new(&entry, &instance)
-                    .create_win32_surface(&info, None)
-                    .unwrap()
+    unsafe fn create(window: Rc<Window>, config: &config::Config) -> Self {
+        let mut app = Self {
+            windows: HashMap::new(),
+            renderer: Renderer::create(&window, config),
+        };
+        app.add_window(window, config.present_modes.clone());
+        app.renderer.data.debug_state.check();
+        app
+    }
+
+    /// Creates the view of `window`. The window is closed if the device can't
+    /// present to it.
+    unsafe fn add_window(&mut self, window: Rc<Window>, present_modes: Vec<vk::PresentModeKHR>) {
+        let Some(view) = View::create(&self.renderer, &window, present_modes) else {
+            return;
+        };
+
+        let pacer = pacing::FramePacer::new(self.renderer.data.config.pacing);
+        window.request_redraw();
+        self.windows.insert(
+            window.id(),
+            AppWindow {
+                view,
+                pacer,
+                window,
+            },
+        );
+    }
+
+    unsafe fn open_window(&mut self, target: &EventLoopWindowTarget<()>) {
+        let config = &self.renderer.data.config;
+        let window = WindowBuilder::new()
+            .with_title(&config.app_name)
+            .with_inner_size(LogicalSize::new(config.width, config.height))
+            .build(target)
+            .unwrap();
+        tracing::info!("opened window {:?}", window.id());
+
+        self.add_window(Rc::new(window), config.present_modes.clone());
+    }
+
+    fn close_window(&mut self, window_id: WindowId) {
+        if self.windows.remove(&window_id).is_some() {
+            tracing::info!("closed window {:?}", window_id);
+        }
+    }
+
+    /// Requests the frames that are due and returns how long the event loop
+    /// waits for events: until the earliest frame of any window.
+    fn update(&mut self) -> ControlFlow {
+        let mut control_flow = ControlFlow::Wait;
+        for window in self.windows.values_mut() {
+            let mut window_control_flow = ControlFlow::Wait;
+            window
+                .pacer
+                .update(&window.window, &mut window_control_flow);
+            control_flow = pacing::earliest(control_flow, window_control_flow);
+        }
+        control_flow
+    }
+
+    /// Renders a frame of the window if it is due.
+    unsafe fn render(&mut self, window_id: WindowId) -> VkResult<()> {
+        let Some(window) = self.windows.get_mut(&window_id) else {
+            return Ok(());
+        };
+
+        if !window.pacer.begin_frame() {
+            return Ok(());
+        }
+
+        window.view.render(&window.window)
+    }
+
+    unsafe fn key_pressed(
+        &mut self,
+        window_id: WindowId,
+        key: VirtualKeyCode,
+        target: &EventLoopWindowTarget<()>,
+    ) {
+        if key == VirtualKeyCode::N {
+            return self.open_window(target);
+        }
+        if key == VirtualKeyCode::F10 {
+            return self.renderer.dump_objects();
+        }
+
+        let Some(window) = self.windows.get_mut(&window_id) else {
+            return;
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        match key {
+            VirtualKeyCode::F12 => {
+                let path = format!("screenshot-{}.png", timestamp);
+                window.view.screenshot(&window.window, Path::new(&path));
             }
-            (RawDisplayHandle::Wayland(display), RawWindowHandle::Wayland(window)) => {
-                let info = vk::WaylandSurfaceCreateInfoKHR::builder()
-                    .display(display.display)
-                    .surface(window.surface)
-                    .build();
-
-                WaylandSurface::new(&entry, &instance)
-                    .create_wayland_surface(&info, None)
-                    .unwrap()
+            VirtualKeyCode::F11 => {
+                let path = format!("graph-{}", timestamp);
+                window.view.dump_graph(Path::new(&path));
             }
-            _ => todo!(),
+            VirtualKeyCode::V => window.view.toggle_vsync(&window.window),
+            _ => (),
+        }
+    }
+
+    /// Logs what is known about the frames in flight of every window when the
+    /// device was lost.
+    unsafe fn report_device_lost(&self) {
+        for window in self.windows.values() {
+            window.view.report_device_lost();
+        }
+    }
+
+    /// Destroys the renderer and the views, and creates them again for the
+    /// same windows, keeping their present mode preferences.
+    unsafe fn recreate_device(self) -> Self {
+        let Self { windows, renderer } = self;
+
+        let windows = windows
+            .into_values()
+            .map(|AppWindow { view, window, .. }| (window, view.data.present_modes.clone()))
+            .collect::<Vec<_>>();
+        let config = renderer.data.config.clone();
+        drop(renderer);
+
+        let mut windows = windows.into_iter();
+        let (window, present_modes) = windows.next().unwrap();
+        let mut app = Self::create(
+            window,
+            &config::Config {
+                present_modes,
+                ..config
+            },
+        );
+        for (window, present_modes) in windows {
+            app.add_window(window, present_modes);
+        }
+        app
+    }
+}
+
+/// The instance and the device, shared by the views of all windows.
+///
+/// Dropping the renderer waits for the device to be idle and destroys all
+/// Vulkan objects, see [`owned`]. The views must be dropped first.
+struct Renderer {
+    /// Device-level state, copied into each view, see [`AppData::share`].
+    /// Headless renderers also own the objects used for offscreen rendering.
+    data: AppData,
+    device: SharedDevice,
+    instance: SharedInstance,
+}
+
+impl Renderer {
+    /// Creates a renderer for windows like `window`. The device is picked to
+    /// present to it.
+    unsafe fn create(window: &Window, config: &config::Config) -> Self {
+        let mut data = AppData {
+            config: config.clone(),
+            ..Default::default()
         };
-        data.surface = surface;
 
-        pick_physical_device(&entry, &instance, &mut data);
+        let instance = create_instance(Some(window), Entry::load().unwrap(), &mut data);
+        let entry = instance.entry();
+
+        // Only used to pick the device, each view creates its own surface.
+        let surface = create_surface(entry, &instance, window);
+        data.surface = owned::Surface::new(&instance, surface);
+
+        pick_physical_device(entry, &instance, &mut data);
+
+        let device = create_logical_device(entry, &instance, &mut data);
+        create_timelines(&device, &mut data);
+        create_index_buffer(&instance, &device, &mut data);
+        data.surface = owned::Surface::default();
+
+        Self {
+            data,
+            device,
+            instance,
+        }
+    }
+
+    /// Creates the renderer without a window, surface or swapchain.
+    ///
+    /// Frames can only be rendered using [`Renderer::render_offscreen`]. Any
+    /// device type is accepted, so this works on software implementations.
+    unsafe fn create_headless(config: &config::Config, extent: vk::Extent2D) -> Self {
+        let mut data = AppData {
+            config: config.clone(),
+            headless: true,
+            ..Default::default()
+        };
+
+        let instance = create_instance(None, Entry::load().unwrap(), &mut data);
+        let entry = instance.entry();
+
+        pick_physical_device(entry, &instance, &mut data);
+
+        let device = create_logical_device(entry, &instance, &mut data);
+        create_timelines(&device, &mut data);
+        create_index_buffer(&instance, &device, &mut data);
+
+        // There is no swapchain, the offscreen target takes its place.
+        data.swapchain_format = vk::Format::R8G8B8A8_SRGB;
+        data.swapchain_extent = extent;
+
+        if !data.dynamic_rendering {
+            create_render_pass(&instance, &device, &mut data);
+        }
+
+        create_pipeline(&device, &mut data);
+        create_command_pool(&device, &mut data);
+
+        Self {
+            data,
+            device,
+            instance,
+        }
+    }
+
+    unsafe fn render_offscreen(&self) -> screenshot::Screenshot {
+        offscreen::render(&self.instance, &self.device, &self.data)
+    }
+
+    /// Logs the table of live Vulkan objects.
+    fn dump_objects(&self) {
+        let registry = self.device.registry();
+        tracing::info!(
+            "{} live Vulkan objects:\n{}",
+            registry.len(),
+            registry.dump()
+        );
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        wait_device_idle(&self.device);
 
-        let device = create_logical_device(&entry, &instance, &mut data);
-        create_swapchain(&entry, window, &instance, &device, &mut data);
+        // Anything still alive once the renderer data is gone was leaked.
+        drop(std::mem::take(&mut self.data));
+        self.device.registry().report_leaks();
+    }
+}
+
+/// The surface, swapchain and frame loop of a window, rendered with the
+/// device of a [`Renderer`].
+///
+/// Dropping the view retires its objects into the deletion queue, they are
+/// destroyed once the frames in flight have completed.
+struct View {
+    data: AppData,
+    device: SharedDevice,
+    instance: SharedInstance,
+    /// Retired with the surface and the swapchain.
+    window: Rc<Window>,
+}
+
+impl View {
+    /// Returns `None` if the present queue of the renderer can't present to
+    /// `window`.
+    unsafe fn create(
+        renderer: &Renderer,
+        window: &Rc<Window>,
+        present_modes: Vec<vk::PresentModeKHR>,
+    ) -> Option<Self> {
+        let mut data = AppData {
+            present_modes,
+            ..renderer.data.share()
+        };
+
+        let instance = renderer.instance.clone();
+        let device = renderer.device.clone();
+        let entry = instance.entry();
+
+        let surface = create_surface(entry, &instance, window);
+        data.surface = owned::Surface::new(&instance, surface);
+
+        let supported = ash::extensions::khr::Surface::new(entry, &instance)
+            .get_physical_device_surface_support(
+                data.physical_device,
+                data.queues.present.family,
+                *data.surface,
+            )
+            .unwrap();
+        if !supported {
+            tracing::error!(
+                "the present queue can't present to window {:?}",
+                window.id()
+            );
+            return None;
+        }
+
+        create_swapchain(
+            entry,
+            window,
+            &instance,
+            &device,
+            &mut data,
+            vk::SwapchainKHR::null(),
+        );
         create_swapchain_image_views(&device, &mut data);
 
-        create_render_pass(&instance, &device, &mut data);
+        if !data.dynamic_rendering {
+            create_render_pass(&instance, &device, &mut data);
+        }
 
         create_pipeline(&device, &mut data);
-        create_framebuffers(&device, &mut data);
-        create_command_pool(&entry, &instance, &device, &mut data);
-        create_command_buffers(&device, &mut data);
+
+        if !data.dynamic_rendering {
+            create_framebuffers(&device, &mut data);
+        }
+
+        create_command_pool(&device, &mut data);
+        create_gpu_timer(&instance, &device, &mut data);
+        create_command_buffers(&instance, &device, &mut data);
 
         create_sync_objects(&device, &mut data);
 
-        Self {
-            entry,
-            instance,
+        Some(Self {
             data,
             device,
-        }
+            instance,
+            window: window.clone(),
+        })
     }
 
-    unsafe fn render(&mut self, window: &Window) {
+    /// Renders a frame. Errors other than `ERROR_DEVICE_LOST` panic.
+    unsafe fn render(&mut self, window: &Window) -> VkResult<()> {
+        let timeline = &self.data.graphics_timeline;
+        let slot = self.data.frame as usize % MAX_FRAMES_IN_FLIGHT;
+
+        // The semaphores of the slot are free once its previous frame has
+        // completed.
+        timeline.wait(&self.device, &self.data, self.data.frame_values[slot])?;
+        let completed = timeline.completed(&self.device, &self.data)?;
+        self.data.deletion_queue.collect(completed);
+
+        let image_available = *self.data.image_available_semaphores[slot];
         let image_index = ash::extensions::khr::Swapchain::new(&self.instance, &self.device)
             .acquire_next_image(
-                self.data.swapchain,
+                *self.data.swapchain,
                 u64::MAX,
-                self.data.image_available_semaphore,
+                image_available,
                 vk::Fence::null(),
-            )
-            .unwrap()
+            )?
             .0 as usize;
 
-        let wait_semaphores = &[self.data.image_available_semaphore];
+        // The command buffer of the image may still be used by an earlier
+        // frame.
+        timeline.wait(
+            &self.device,
+            &self.data,
+            self.data.image_values[image_index],
+        )?;
 
-        let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let mut wait_semaphores = vec![barrier::SemaphoreSubmit::binary(
+            image_available,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        )];
+        wait_semaphores.extend(index_buffer_dependency(&self.device, &self.data)?);
 
-        let command_buffers = &[self.data.command_buffers[image_index as usize]];
+        let command_buffers = &[self.data.command_buffers[image_index]];
 
-        let signal_semaphores = &[self.data.render_finished_semaphore];
+        if let Some(timer) = &mut self.data.gpu_timer {
+            timer.submit(&self.device, image_index, self.data.frame);
+        }
 
-        let submit_info = vk::SubmitInfo::builder()
-            .wait_semaphores(wait_semaphores)
-            .wait_dst_stage_mask(wait_stages)
-            .command_buffers(command_buffers)
-            .signal_semaphores(signal_semaphores)
-            .build();
+        let render_finished = *self.data.render_finished_semaphores[image_index];
+        let signal_semaphores = &[barrier::SemaphoreSubmit::binary(
+            render_finished,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+        )];
 
-        self.device
-            .queue_submit(self.data.graphics_queue, &[submit_info], vk::Fence::null())
-            .unwrap();
+        if let Some(breadcrumbs) = &self.data.breadcrumbs {
+            breadcrumbs.reset(image_index);
+        }
+
+        // A requested screenshot is copied after rendering, before the image
+        // is presented.
+        let capture = self.data.screenshot_path.take().map(|path| {
+            let capture =
+                screenshot::record_capture(&self.instance, &self.device, &self.data, image_index);
+            (path, capture)
+        });
+        let (frame_signal_semaphores, capture_signal_semaphores) = match capture {
+            Some(_) => (&[][..], &signal_semaphores[..]),
+            None => (&signal_semaphores[..], &[][..]),
+        };
+
+        let mut value = self.data.graphics_timeline.submit(
+            &self.device,
+            &self.data,
+            &wait_semaphores,
+            command_buffers,
+            frame_signal_semaphores,
+        )?;
+        self.data.debug_state.check();
+
+        if let Some(breadcrumbs) = &mut self.data.breadcrumbs {
+            breadcrumbs.submitted(image_index, self.data.frame, value);
+        }
+
+        if let Some((_, capture)) = &capture {
+            value = self.data.graphics_timeline.submit(
+                &self.device,
+                &self.data,
+                &[],
+                &[capture.command_buffer],
+                capture_signal_semaphores,
+            )?;
+        }
+        self.data.frame_values[slot] = value;
+        self.data.image_values[image_index] = value;
 
-        let swapchains = &[self.data.swapchain];
+        let signal_semaphores = &[render_finished];
+
+        let swapchains = &[*self.data.swapchain];
         let image_indices = &[image_index as u32];
         let present_info = vk::PresentInfoKHR::builder()
             .wait_semaphores(signal_semaphores)
@@ -173,60 +623,195 @@ impl App {
             .image_indices(image_indices);
 
         ash::extensions::khr::Swapchain::new(&self.instance, &self.device)
-            .queue_present(self.data.present_queue, &present_info)
-            .unwrap();
+            .queue_present(self.data.queues.present.handle, &present_info)?;
+        self.data.debug_state.check();
 
-        self.device
-            .queue_wait_idle(self.data.present_queue)
-            .unwrap();
+        self.data.last_image_index = Some(image_index);
+        self.data.frame += 1;
+
+        if let Some((path, capture)) = capture {
+            self.data
+                .graphics_timeline
+                .wait(&self.device, &self.data, value)?;
+            save_screenshot(&path, &capture.finish(&self.device, &self.data));
+        }
+
+        Ok(())
     }
 
-    unsafe fn destroy(&mut self) {
-        self.device
-            .destroy_semaphore(self.data.render_finished_semaphore, None);
-        self.device
-            .destroy_semaphore(self.data.image_available_semaphore, None);
+    /// Rebuilds the swapchain and everything depending on it, e.g. after the
+    /// present mode preference changed.
+    unsafe fn recreate_swapchain(&mut self, window: &Window) {
+        use std::mem::take;
+
+        // Frames in flight may still use the old objects, they are retired
+        // instead of waiting for the queues to be idle. The command buffers
+        // are freed with their pool.
+        let data = &mut self.data;
+        let old_swapchain = take(&mut data.swapchain);
+        let retired = (
+            take(&mut data.graph_transients),
+            data.breadcrumbs.take(),
+            data.gpu_timer.take(),
+            take(&mut data.command_pool),
+            take(&mut data.image_available_semaphores),
+            take(&mut data.render_finished_semaphores),
+            take(&mut data.pipeline),
+            take(&mut data.pipeline_layout),
+            take(&mut data.framebuffers),
+            take(&mut data.render_pass),
+            take(&mut data.swapchain_image_view),
+        );
+        data.command_buffers.clear();
+        data.last_image_index = None;
 
-        self.device
-            .destroy_command_pool(self.data.command_pool, None);
+        let entry = self.instance.entry();
+        create_swapchain(
+            entry,
+            window,
+            &self.instance,
+            &self.device,
+            data,
+            *old_swapchain,
+        );
 
-        self.data
-            .framebuffers
-            .iter()
-            .for_each(|f| self.device.destroy_framebuffer(*f, None));
+        // The old swapchain is dropped after the objects using it.
+        data.deletion_queue
+            .retire(retire_value(data), (retired, old_swapchain));
 
-        self.device.destroy_pipeline(self.data.pipeline, None);
+        create_swapchain_image_views(&self.device, data);
 
-        self.device
-            .destroy_pipeline_layout(self.data.pipeline_layout, None);
-        self.device.destroy_render_pass(self.data.render_pass, None);
+        if !data.dynamic_rendering {
+            create_render_pass(&self.instance, &self.device, data);
+        }
 
-        self.data
-            .swapchain_image_view
-            .iter()
-            .for_each(|v| self.device.destroy_image_view(*v, None));
+        create_pipeline(&self.device, data);
 
-        ash::extensions::khr::Swapchain::new(&self.instance, &self.device)
-            .destroy_swapchain(self.data.swapchain, None);
+        if !data.dynamic_rendering {
+            create_framebuffers(&self.device, data);
+        }
+
+        create_command_pool(&self.device, data);
+        create_gpu_timer(&self.instance, &self.device, data);
+        create_command_buffers(&self.instance, &self.device, data);
+        create_sync_objects(&self.device, data);
+    }
+
+    /// Switches between present modes with and without vsync.
+    unsafe fn toggle_vsync(&mut self, window: &Window) {
+        let preference = if present::is_vsync(self.data.present_mode) {
+            present::NO_VSYNC
+        } else {
+            present::VSYNC
+        };
+
+        self.data.present_modes = preference.to_vec();
+        self.recreate_swapchain(window);
+    }
+
+    /// Logs what is known about the frames in flight when the device was
+    /// lost.
+    unsafe fn report_device_lost(&self) {
+        match &self.data.breadcrumbs {
+            Some(breadcrumbs) => breadcrumbs.report(&self.device, &self.data),
+            None => tracing::error!("device lost"),
+        }
+    }
+
+    /// Writes the next frame to `path` as a PNG.
+    ///
+    /// The swapchain image is copied when the frame is rendered if the surface
+    /// allows `TRANSFER_SRC` usage, otherwise the scene is rendered again into
+    /// an offscreen image right away.
+    unsafe fn screenshot(&mut self, window: &Window, path: &Path) {
+        if self
+            .data
+            .swapchain_usage
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+        {
+            self.data.screenshot_path = Some(path.to_owned());
+            window.request_redraw();
+            return;
+        }
+
+        tracing::debug!("swapchain does not support TRANSFER_SRC, capturing offscreen");
+        let screenshot = offscreen::render(&self.instance, &self.device, &self.data);
+        save_screenshot(path, &screenshot);
+    }
+
+    /// Writes the render graph of the last rendered frame to `<prefix>.dot`
+    /// and `<prefix>.json`.
+    fn dump_graph(&self, prefix: &Path) {
+        let index = self.data.last_image_index.unwrap_or_default();
+        let Some(transients) = self.data.graph_transients.get(index) else {
+            return;
+        };
+
+        if let Err(err) = transients.description().write(prefix) {
+            tracing::error!("failed to write render graph: {}", err);
+        }
+    }
+}
+
+fn save_screenshot(path: &Path, screenshot: &screenshot::Screenshot) {
+    match screenshot::write_png(path, screenshot) {
+        Ok(()) => tracing::info!("saved screenshot to {}", path.display()),
+        Err(err) => tracing::error!("failed to save screenshot: {}", err),
+    }
+}
 
-        self.device.destroy_device(None);
+impl Drop for View {
+    fn drop(&mut self) {
+        // Frames in flight may still use the objects of the view. The
+        // retired data must not keep the queue itself alive, and is dropped
+        // before the window its surface was created from.
+        let mut data = std::mem::take(&mut self.data);
+        let deletion_queue = std::mem::take(&mut data.deletion_queue);
+        let value = retire_value(&data);
+        deletion_queue.retire(value, (data, self.window.clone()));
+    }
+}
 
-        let debug_utils = DebugUtils::new(&self.entry, &self.instance);
-        debug_utils.destroy_debug_utils_messenger(self.data.messenger, None);
+/// Returns the graphics timeline value after which objects used by the frames
+/// submitted so far can be destroyed.
+///
+/// Presentation has no completion signal of its own. Objects it uses, e.g.
+/// the semaphore it waits on, are kept until a later submit to the graphics
+/// queue, which presentation shares unless the families differ, has
+/// completed.
+fn retire_value(data: &AppData) -> u64 {
+    data.graphics_timeline.submitted() + 1
+}
 
-        ash::extensions::khr::Surface::new(&self.entry, &self.instance)
-            .destroy_surface(self.data.surface, None);
-        self.instance.destroy_instance(None);
+/// Waits until no objects are used by frames in flight. Nothing is in flight
+/// anymore if the device was lost.
+fn wait_device_idle(device: &Device) {
+    match unsafe { device.device_wait_idle() } {
+        Ok(()) | Err(vk::Result::ERROR_DEVICE_LOST) => (),
+        Err(err) => panic!("failed to wait for the device: {}", err),
     }
 }
 
-unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) -> Instance {
+unsafe fn create_instance(
+    window: Option<&Window>,
+    entry: Entry,
+    data: &mut AppData,
+) -> SharedInstance {
+    data.debug_state = Rc::new(debug::DebugState::new(data.config.validation.clone()));
+
+    data.instance_version = features::instance_version(&entry);
+    tracing::info!(
+        "using instance API version {}",
+        features::format_version(data.instance_version)
+    );
+
+    let app_name = CString::new(data.config.app_name.as_str()).unwrap();
     let app_info = ApplicationInfo::builder()
-        .application_name(CStr::from_bytes_with_nul(b"Hello Vulkan\0").unwrap())
+        .application_name(&app_name)
         .application_version(make_version(0, 1, 0))
         .engine_name(CStr::from_bytes_with_nul(b"vk\0").unwrap())
         .engine_version(make_version(0, 1, 0))
-        .api_version(make_version(1, 0, 0));
+        .api_version(data.instance_version);
 
     let available_layers = entry
         .enumerate_instance_layer_properties()
@@ -244,18 +829,79 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
         )
     };
 
-    if !available_layers.contains(&validation_layer) {
+    let validation = !data.debug_state.config().disabled;
+    if validation && !available_layers.contains(&validation_layer) {
         panic!("validation layer not supported");
     }
 
-    let layers = vec![VALIDATION_LAYER.as_ptr()];
+    let layers = validation_layers(data);
 
-    let extensions = get_required_instance_extensions(window)
+    let available = available_instance_extensions(&entry, !layers.is_empty());
+
+    let required = match window {
+        Some(window) => {
+            get_required_instance_extensions(window).unwrap_or_else(|err| panic!("{}", err))
+        }
+        None => vec![],
+    };
+    let missing = required
         .iter()
-        .map(|e| e.as_ptr())
+        .filter(|e| !available.contains(**e))
+        .map(|e| e.to_string_lossy())
         .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        panic!(
+            "missing required instance extensions: {}",
+            missing.join(", ")
+        );
+    }
 
-    let flags = InstanceCreateFlags::empty();
+    let mut enabled = required;
+    let optional = OPTIONAL_INSTANCE_EXTENSIONS
+        .iter()
+        .chain(window.map_or(&[][..], |_| OPTIONAL_SURFACE_EXTENSIONS));
+    for name in optional {
+        if available.contains(*name) {
+            enabled.push(name);
+        } else {
+            tracing::info!(
+                "optional instance extension {} not supported",
+                name.to_string_lossy()
+            );
+        }
+    }
+    tracing::info!("enabled instance extensions: {:?}", enabled);
+
+    let debug_utils = enabled.contains(&DebugUtils::name());
+    let mut extensions = enabled.iter().map(|e| e.as_ptr()).collect::<Vec<_>>();
+
+    // VK_EXT_validation_features is provided by the validation layer.
+    let mut validation_features = data.debug_state.config().enabled_features();
+    if !validation_features.is_empty() {
+        let layer_extensions = entry
+            .enumerate_instance_extension_properties(Some(VALIDATION_LAYER))
+            .unwrap();
+
+        if layer_extensions
+            .iter()
+            .any(|e| read_cstr(&e.extension_name) == vk::ExtValidationFeaturesFn::name())
+        {
+            extensions.push(vk::ExtValidationFeaturesFn::name().as_ptr());
+        } else {
+            tracing::warn!("VK_EXT_validation_features not supported");
+            validation_features.clear();
+        }
+    }
+
+    let mut validation_features_info =
+        vk::ValidationFeaturesEXT::builder().enabled_validation_features(&validation_features);
+
+    // Lists devices that are not fully conformant, e.g. MoltenVK.
+    let flags = if enabled.contains(&vk::KhrPortabilityEnumerationFn::name()) {
+        InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
+    } else {
+        InstanceCreateFlags::empty()
+    };
 
     let mut info = InstanceCreateInfo::builder()
         .application_info(&app_info)
@@ -276,104 +922,283 @@ unsafe fn create_instance(window: &Window, entry: &Entry, data: &mut AppData) ->
                 | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
                 | DebugUtilsMessageTypeFlagsEXT::VALIDATION,
         )
-        .pfn_user_callback(Some(debug_callback));
+        .pfn_user_callback(Some(debug::debug_callback))
+        .user_data(Rc::as_ptr(&data.debug_state) as *mut c_void);
+
+    if debug_utils {
+        info = info.push_next(&mut debug_info);
+    }
 
-    info = info.push_next(&mut debug_info);
+    if !validation_features.is_empty() {
+        tracing::info!("enabled validation features: {:?}", validation_features);
+        info = info.push_next(&mut validation_features_info);
+    }
 
     let instance = entry.create_instance(&info, None).unwrap();
 
-    let debug_utils = DebugUtils::new(entry, &instance);
-    data.messenger = debug_utils
-        .create_debug_utils_messenger(&debug_info, None)
-        .unwrap();
+    // Validation messages are only logged with VK_EXT_debug_utils.
+    let messenger = debug_utils.then(|| {
+        let debug_utils = DebugUtils::new(&entry, &instance);
+        let messenger = debug_utils
+            .create_debug_utils_messenger(&debug_info, None)
+            .unwrap();
+        (debug_utils, messenger)
+    });
+    data.debug_utils = messenger
+        .as_ref()
+        .map(|(debug_utils, _)| debug_utils.clone());
 
-    instance
+    owned::InstanceOwner::new(entry, instance, messenger, data.debug_state.clone())
 }
 
-pub fn get_required_instance_extensions<'a>(
-    window: &'a dyn HasRawWindowHandle,
-) -> &'static [&'static CStr] {
-    match window.raw_window_handle() {
-        RawWindowHandle::Wayland(_) => WAYLAND,
-        RawWindowHandle::Xcb(_) => XCB,
-        RawWindowHandle::Xlib(_) => XLIB,
-        _ => todo!(),
+/// Returns the names of the instance extensions provided by the
+/// implementation and, with `validation`, the validation layer.
+fn available_instance_extensions(entry: &Entry, validation: bool) -> HashSet<CString> {
+    let mut available = entry.enumerate_instance_extension_properties(None).unwrap();
+    if validation {
+        available.extend(
+            entry
+                .enumerate_instance_extension_properties(Some(VALIDATION_LAYER))
+                .unwrap(),
+        );
     }
+
+    available
+        .iter()
+        .map(|e| read_cstr(&e.extension_name).to_owned())
+        .collect()
 }
 
-const WAYLAND: &'static [&'static CStr] = &[
-    ash::extensions::khr::Surface::name(),
-    ash::extensions::khr::WaylandSurface::name(),
-    DebugUtils::name(),
-];
+/// Returns the instance extensions needed to create surfaces for windows on
+/// the display of `window`.
+pub fn get_required_instance_extensions(
+    window: &dyn HasRawDisplayHandle,
+) -> Result<Vec<&'static CStr>, String> {
+    let platform = match window.raw_display_handle() {
+        RawDisplayHandle::Wayland(_) => WaylandSurface::name(),
+        RawDisplayHandle::Xcb(_) => XcbSurface::name(),
+        RawDisplayHandle::Xlib(_) => XlibSurface::name(),
+        RawDisplayHandle::Windows(_) => Win32Surface::name(),
+        handle => return Err(format!("unsupported display {:?}", handle)),
+    };
 
-const XCB: &'static [&'static CStr] = &[
-    ash::extensions::khr::Surface::name(),
-    ash::extensions::khr::XcbSurface::name(),
-    DebugUtils::name(),
-];
+    Ok(vec![ash::extensions::khr::Surface::name(), platform])
+}
 
-const XLIB: &'static [&'static CStr] = &[
-    ash::extensions::khr::Surface::name(),
-    ash::extensions::khr::XlibSurface::name(),
-    DebugUtils::name(),
+/// Instance extensions enabled if available.
+const OPTIONAL_INSTANCE_EXTENSIONS: &[&CStr] =
+    &[DebugUtils::name(), vk::KhrPortabilityEnumerationFn::name()];
+
+/// Instance extensions enabled if available when rendering to a window.
+const OPTIONAL_SURFACE_EXTENSIONS: &[&CStr] = &[
+    vk::KhrGetSurfaceCapabilities2Fn::name(),
+    // Needed for surface color spaces other than sRGB.
+    vk::ExtSwapchainColorspaceFn::name(),
 ];
 
 const VALIDATION_LAYER: &'static CStr =
     unsafe { CStr::from_bytes_with_nul_unchecked(b"VK_LAYER_KHRONOS_validation\0") };
 
-extern "system" fn debug_callback(
-    severity: DebugUtilsMessageSeverityFlagsEXT,
-    type_: DebugUtilsMessageTypeFlagsEXT,
-    data: *const DebugUtilsMessengerCallbackDataEXT,
-    _: *mut c_void,
-) -> Bool32 {
-    let data = unsafe { *data };
-    let message = unsafe { CStr::from_ptr(data.p_message) }.to_string_lossy();
-
-    if severity >= DebugUtilsMessageSeverityFlagsEXT::ERROR {
-        tracing::error!("({:?}) {}", type_, message);
-    } else if severity >= DebugUtilsMessageSeverityFlagsEXT::WARNING {
-        tracing::warn!("({:?}) {}", type_, message);
-    } else if severity >= DebugUtilsMessageSeverityFlagsEXT::INFO {
-        tracing::debug!("({:?}) {}", type_, message);
+/// Layers to enable, empty if validation is disabled.
+fn validation_layers(data: &AppData) -> Vec<*const c_char> {
+    if data.debug_state.config().disabled {
+        vec![]
     } else {
-        tracing::trace!("({:?}) {}", type_, message);
+        vec![VALIDATION_LAYER.as_ptr()]
     }
-
-    vk::FALSE
 }
 
 #[derive(Default)]
 struct AppData {
-    messenger: DebugUtilsMessengerEXT,
+    config: config::Config,
+    /// Rendering offscreen only, `surface` is null.
+    headless: bool,
+    debug_utils: Option<DebugUtils>,
+    /// Shared with the debug messenger, see [`owned::InstanceOwner`].
+    debug_state: Rc<debug::DebugState>,
     physical_device: vk::PhysicalDevice,
-    graphics_queue: vk::Queue,
-    surface: SurfaceKHR,
-    present_queue: vk::Queue,
-    swapchain: vk::SwapchainKHR,
+    /// API version requested from the instance.
+    instance_version: u32,
+    /// Features enabled on the device.
+    features: features::DeviceFeatures,
+    /// Queues by role, see [`queues`].
+    queues: queues::Queues,
     swapchain_images: Vec<vk::Image>,
     swapchain_format: vk::Format,
+    swapchain_color_space: vk::ColorSpaceKHR,
     swapchain_extent: vk::Extent2D,
-    swapchain_image_view: Vec<vk::ImageView>,
-    pipeline_layout: vk::PipelineLayout,
-    render_pass: vk::RenderPass,
-    pipeline: vk::Pipeline,
-    framebuffers: Vec<vk::Framebuffer>,
-    command_pool: vk::CommandPool,
+    swapchain_usage: vk::ImageUsageFlags,
+    /// Present modes in order of preference, see [`present`].
+    present_modes: Vec<vk::PresentModeKHR>,
+    present_mode: vk::PresentModeKHR,
+    /// Transfer function applied by the fragment shader for the swapchain
+    /// format.
+    output_transfer: surface_format::OutputTransfer,
+    /// `VK_EXT_hdr_metadata` if supported.
+    hdr_metadata_ext: Option<vk::ExtHdrMetadataFn>,
+    /// `VK_AMD_buffer_marker` if supported, used by [`breadcrumbs`].
+    buffer_marker_ext: Option<vk::AmdBufferMarkerFn>,
+    /// Render with `cmd_begin_rendering` instead of `render_pass` and
+    /// `framebuffers`, which are not created.
+    dynamic_rendering: bool,
+    /// Loader for `VK_KHR_dynamic_rendering` if it is not core.
+    dynamic_rendering_ext: Option<ash::extensions::khr::DynamicRendering>,
+    /// Record barriers and submit with synchronization2, see [`barrier`].
+    synchronization2: bool,
+    /// Loader for `VK_KHR_synchronization2` if it is not core.
+    synchronization2_ext: Option<ash::extensions::khr::Synchronization2>,
+    /// Use timeline semaphores for [`timeline::Timeline`].
+    timeline_semaphore: bool,
+    /// Loader for `VK_KHR_timeline_semaphore` if it is not core.
+    timeline_semaphore_ext: Option<ash::extensions::khr::TimelineSemaphore>,
     command_buffers: Vec<vk::CommandBuffer>,
-    image_available_semaphore: vk::Semaphore,
-    render_finished_semaphore: vk::Semaphore,
+    last_image_index: Option<usize>,
+    /// Where to save a screenshot of the next frame, see
+    /// [`screenshot::record_capture`].
+    screenshot_path: Option<PathBuf>,
+    /// Number of frames submitted so far.
+    frame: u64,
+    /// Graphics timeline value of the last submit by frame in flight.
+    frame_values: Vec<u64>,
+    /// Graphics timeline value of the last submit by swapchain image.
+    image_values: Vec<u64>,
+
+    // Owned objects, dropped in declaration order: users before the objects
+    // they use.
+    /// Shared by the renderer and all views, see [`deletion`].
+    deletion_queue: Rc<deletion::DeletionQueue>,
+    /// Submits to the queues, shared by the renderer and all views. Roles
+    /// using the same queue share its timeline.
+    graphics_timeline: Rc<timeline::Timeline>,
+    compute_timeline: Rc<timeline::Timeline>,
+    transfer_timeline: Rc<timeline::Timeline>,
+    /// Indices of the scene, uploaded through the transfer queue.
+    index_buffer: Rc<upload::UploadedBuffer>,
+    /// Transient resources of the render graphs recorded into
+    /// `command_buffers`.
+    graph_transients: Vec<graph::Transients>,
+    gpu_timer: Option<timing::GpuTimer>,
+    breadcrumbs: Option<breadcrumbs::Breadcrumbs>,
+    command_pool: Owned<vk::CommandPool>,
+    /// By frame in flight.
+    image_available_semaphores: Vec<Owned<vk::Semaphore>>,
+    /// By swapchain image, presentation may still wait on the semaphore of an
+    /// image until it is acquired again.
+    render_finished_semaphores: Vec<Owned<vk::Semaphore>>,
+    pipeline: Owned<vk::Pipeline>,
+    pipeline_layout: Owned<vk::PipelineLayout>,
+    framebuffers: Vec<Owned<vk::Framebuffer>>,
+    render_pass: Owned<vk::RenderPass>,
+    swapchain_image_view: Vec<Owned<vk::ImageView>>,
+    swapchain: Owned<vk::SwapchainKHR>,
+    surface: owned::Surface,
+}
+
+impl AppData {
+    /// Returns the device-level state for another user of the device, e.g.
+    /// the view of a window. Of the objects, only the timelines, the deletion
+    /// queue and the index buffer are shared: all submits to a queue go
+    /// through the same timeline.
+    fn share(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            headless: self.headless,
+            debug_utils: self.debug_utils.clone(),
+            debug_state: self.debug_state.clone(),
+            physical_device: self.physical_device,
+            instance_version: self.instance_version,
+            features: self.features.clone(),
+            queues: self.queues,
+            present_modes: self.present_modes.clone(),
+            hdr_metadata_ext: self.hdr_metadata_ext.clone(),
+            buffer_marker_ext: self.buffer_marker_ext.clone(),
+            dynamic_rendering: self.dynamic_rendering,
+            dynamic_rendering_ext: self.dynamic_rendering_ext.clone(),
+            synchronization2: self.synchronization2,
+            synchronization2_ext: self.synchronization2_ext.clone(),
+            timeline_semaphore: self.timeline_semaphore,
+            timeline_semaphore_ext: self.timeline_semaphore_ext.clone(),
+            graphics_timeline: self.graphics_timeline.clone(),
+            compute_timeline: self.compute_timeline.clone(),
+            transfer_timeline: self.transfer_timeline.clone(),
+            index_buffer: self.index_buffer.clone(),
+            deletion_queue: self.deletion_queue.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+unsafe fn create_surface(entry: &Entry, instance: &Instance, window: &Window) -> vk::SurfaceKHR {
+    match (window.raw_display_handle(), window.raw_window_handle()) {
+        (RawDisplayHandle::Xcb(display), RawWindowHandle::Xcb(window)) => {
+            let info = vk::XcbSurfaceCreateInfoKHR::builder()
+                .window(window.window)
+                .connection(display.connection)
+                .build();
+
+            XcbSurface::new(entry, instance)
+                .create_xcb_surface(&info, None)
+                .unwrap()
+        }
+        (RawDisplayHandle::Xlib(display), RawWindowHandle::Xlib(window)) => {
+            let info = vk::XlibSurfaceCreateInfoKHR::builder()
+                .window(window.window)
+                .dpy(display.display as *mut _)
+                .build();
+
+            XlibSurface::new(entry, instance)
+                .create_xlib_surface(&info, None)
+                .unwrap()
+        }
+        (RawDisplayHandle::Windows(_), RawWindowHandle::Win32(window)) => {
+            let info = vk::Win32SurfaceCreateInfoKHR::builder()
+                .hinstance(window.hinstance)
+                .hwnd(window.hwnd);
+
+            Win32Surface::new(entry, instance)
+                .create_win32_surface(&info, None)
+                .unwrap()
+        }
+        (RawDisplayHandle::Wayland(display), RawWindowHandle::Wayland(window)) => {
+            let info = vk::WaylandSurfaceCreateInfoKHR::builder()
+                .display(display.display)
+                .surface(window.surface)
+                .build();
+
+            WaylandSurface::new(entry, instance)
+                .create_wayland_surface(&info, None)
+                .unwrap()
+        }
+        (display, window) => panic!("unsupported window {:?} on {:?}", window, display),
+    }
 }
 
 unsafe fn pick_physical_device(entry: &Entry, instance: &Instance, data: &mut AppData) {
-    for physical_device in instance.enumerate_physical_devices().unwrap() {
+    for (index, physical_device) in instance
+        .enumerate_physical_devices()
+        .unwrap()
+        .into_iter()
+        .enumerate()
+    {
         let properties = instance.get_physical_device_properties(physical_device);
 
         let name = read_cstr(&properties.device_name);
 
-        if !check_physical_device(entry, instance, data, physical_device) {
-            tracing::warn!("physical device not suitable: {}", name.to_string_lossy());
+        if !is_configured_device(data, index, name) {
+            tracing::info!(
+                "physical device not selected by configuration: {}",
+                name.to_string_lossy()
+            );
+            continue;
+        }
+
+        let problems = physical_device_problems(entry, instance, data, physical_device);
+        if !problems.is_empty() {
+            tracing::warn!(
+                "physical device not suitable: {}: {}",
+                name.to_string_lossy(),
+                problems.join(", ")
+            );
         } else {
             tracing::info!("selected device: {}", name.to_string_lossy());
 
@@ -386,77 +1211,60 @@ unsafe fn pick_physical_device(entry: &Entry, instance: &Instance, data: &mut Ap
     panic!("no device selected");
 }
 
-unsafe fn check_physical_device(
+/// Returns whether the device at `index` named `name` matches the configured
+/// device, if any.
+fn is_configured_device(data: &AppData, index: usize, name: &CStr) -> bool {
+    let Some(device) = &data.config.device else {
+        return true;
+    };
+
+    match device.parse::<usize>() {
+        Ok(i) => i == index,
+        Err(_) => name
+            .to_string_lossy()
+            .to_lowercase()
+            .contains(&device.to_lowercase()),
+    }
+}
+
+/// Returns why `physical_device` can't be used, empty if it is suitable.
+unsafe fn physical_device_problems(
     entry: &Entry,
     instance: &Instance,
     data: &AppData,
     physical_device: vk::PhysicalDevice,
-) -> bool {
-    let properties = instance.get_physical_device_properties(physical_device);
-    if properties.device_type != vk::PhysicalDeviceType::DISCRETE_GPU {
-        tracing::warn!("no DGPU");
-        return false;
-    }
+) -> Vec<String> {
+    let mut problems = Vec::new();
 
-    let features = instance.get_physical_device_features(physical_device);
-    if features.geometry_shader != vk::TRUE {
-        tracing::warn!("no geometry shader");
-        return false;
+    let properties = instance.get_physical_device_properties(physical_device);
+    if !data.headless && properties.device_type != vk::PhysicalDeviceType::DISCRETE_GPU {
+        problems.push("no DGPU".to_owned());
     }
 
-    if QueueFamilyIndices::get(entry, instance, data, physical_device).is_none() {
-        tracing::warn!("missing queue families");
-        return false;
+    let features =
+        features::DeviceFeatures::query(instance, data.instance_version, physical_device);
+    let missing = features.missing(&FEATURES);
+    if !missing.is_empty() {
+        problems.push(format!("missing features: {:?}", missing));
     }
 
-    if !check_physical_device_extensions(instance, physical_device) {
-        return false;
+    if queues::QueueFamilies::get(entry, instance, data, physical_device).is_none() {
+        problems.push("missing queue families".to_owned());
     }
 
-    let support = SwapchainSupport::get(entry, instance, data, physical_device);
-    if support.formats.is_empty() || support.present_modes.is_empty() {
-        tracing::warn!("no formats or present modes");
-        return false;
+    let missing = missing_device_extensions(instance, physical_device);
+    if !missing.is_empty() {
+        problems.push(format!("missing extensions: {:?}", missing));
     }
 
-    true
-}
-
-struct QueueFamilyIndices {
-    graphics: u32,
-    present: u32,
-}
-
-impl QueueFamilyIndices {
-    unsafe fn get(
-        entry: &Entry,
-        instance: &Instance,
-        data: &AppData,
-        physical_device: vk::PhysicalDevice,
-    ) -> Option<Self> {
-        let properties = instance.get_physical_device_queue_family_properties(physical_device);
-
-        let graphics = properties
-            .iter()
-            .position(|p| p.queue_flags.contains(vk::QueueFlags::GRAPHICS))
-            .map(|i| i as u32);
-
-        let mut present = None;
-        for (index, properties) in properties.iter().enumerate() {
-            if ash::extensions::khr::Surface::new(&entry, &instance)
-                .get_physical_device_surface_support(physical_device, index as u32, data.surface)
-                .unwrap()
-            {
-                present = Some(index as u32);
-                break;
-            }
+    if !data.headless {
+        let support = SwapchainSupport::get(entry, instance, data, physical_device);
+        if support.formats.is_empty() || support.present_modes.is_empty() {
+            problems.push("no formats or present modes".to_owned());
         }
-
-        Some(Self {
-            graphics: graphics?,
-            present: present?,
-        })
     }
+
+    problems
 }
 
 fn read_cstr(buf: &[i8]) -> &CStr {
@@ -474,55 +1282,257 @@ fn read_cstr(buf: &[i8]) -> &CStr {
     CStr::from_bytes_with_nul(&buf[0..null + 1]).unwrap()
 }
 
-unsafe fn create_logical_device(entry: &Entry, instance: &Instance, data: &mut AppData) -> Device {
-    let indices = QueueFamilyIndices::get(entry, instance, data, data.physical_device).unwrap();
-
-    let mut unique_indices = HashSet::new();
-    unique_indices.insert(indices.graphics);
-    unique_indices.insert(indices.present);
-
-    let queue_priorities = &[1.0];
-    let queue_infos = unique_indices
+unsafe fn create_logical_device(
+    entry: &Entry,
+    instance: &SharedInstance,
+    data: &mut AppData,
+) -> SharedDevice {
+    let families = queues::QueueFamilies::get(entry, instance, data, data.physical_device).unwrap();
+    let properties = instance.get_physical_device_queue_family_properties(data.physical_device);
+    let allocation = families.allocate(&properties);
+    tracing::info!("using queue families {:?}", families);
+
+    let queue_priorities = [1.0; 4];
+    let queue_infos = allocation
+        .counts
         .iter()
-        .map(|i| {
+        .map(|(family, count)| {
             vk::DeviceQueueCreateInfo::builder()
-                .queue_family_index(*i)
-                .queue_priorities(queue_priorities)
+                .queue_family_index(*family)
+                .queue_priorities(&queue_priorities[..*count as usize])
                 .build()
         })
         .collect::<Vec<_>>();
 
-    let layers = vec![VALIDATION_LAYER.as_ptr()];
+    let layers = validation_layers(data);
 
     let mut extensions = DEVICE_EXTENSIONS
         .iter()
         .map(|n| n.as_ptr())
         .collect::<Vec<_>>();
 
-    let features = vk::PhysicalDeviceFeatures::builder();
+    // Required by shaders using debugPrintfEXT.
+    if data.debug_state.config().debug_printf {
+        let name = vk::KhrShaderNonSemanticInfoFn::name();
+        let supported = instance
+            .enumerate_device_extension_properties(data.physical_device)
+            .unwrap()
+            .iter()
+            .any(|e| read_cstr(&e.extension_name) == name);
+
+        if supported {
+            extensions.push(name.as_ptr());
+        } else {
+            tracing::warn!("{} not supported", name.to_string_lossy());
+        }
+    }
+
+    let hdr_metadata = !data.headless && {
+        let name = vk::ExtHdrMetadataFn::name();
+        let supported = instance
+            .enumerate_device_extension_properties(data.physical_device)
+            .unwrap()
+            .iter()
+            .any(|e| read_cstr(&e.extension_name) == name);
+
+        if supported {
+            extensions.push(name.as_ptr());
+        }
+        supported
+    };
+
+    let buffer_marker = {
+        let name = vk::AmdBufferMarkerFn::name();
+        let supported = instance
+            .enumerate_device_extension_properties(data.physical_device)
+            .unwrap()
+            .iter()
+            .any(|e| read_cstr(&e.extension_name) == name);
+
+        if supported {
+            extensions.push(name.as_ptr());
+        }
+        supported
+    };
 
-    let info = vk::DeviceCreateInfo::builder()
+    // Must be enabled if supported, by non-conformant implementations.
+    let portability_subset = vk::KhrPortabilitySubsetFn::name();
+    if instance
+        .enumerate_device_extension_properties(data.physical_device)
+        .unwrap()
+        .iter()
+        .any(|e| read_cstr(&e.extension_name) == portability_subset)
+    {
+        extensions.push(portability_subset.as_ptr());
+    }
+
+    let supported =
+        features::DeviceFeatures::query(instance, data.instance_version, data.physical_device);
+    data.features = supported.select(&FEATURES);
+
+    tracing::info!(
+        "using device API version {}",
+        features::format_version(data.features.api_version)
+    );
+    for feature in FEATURES.optional {
+        if !data.features.is_enabled(*feature) {
+            tracing::info!("optional feature {:?} not supported", feature);
+        }
+    }
+
+    extensions.extend(data.features.extensions.iter().map(|e| e.as_ptr()));
+
+    let mut enabled = data.features.clone();
+    let mut features2 = enabled.chain();
+
+    let mut info = vk::DeviceCreateInfo::builder()
         .queue_create_infos(&queue_infos)
         .enabled_layer_names(&layers)
-        .enabled_extension_names(&extensions)
-        .enabled_features(&features);
+        .enabled_extension_names(&extensions);
+
+    // VkPhysicalDeviceFeatures2 replaces pEnabledFeatures.
+    if data.instance_version >= vk::API_VERSION_1_1 {
+        info = info.push_next(&mut features2);
+    } else {
+        info = info.enabled_features(&data.features.features);
+    }
 
     let device = instance
         .create_device(data.physical_device, &info, None)
         .unwrap();
 
-    data.graphics_queue = device.get_device_queue(indices.graphics, 0);
-    data.present_queue = device.get_device_queue(indices.present, 0);
+    if hdr_metadata {
+        data.hdr_metadata_ext = Some(vk::ExtHdrMetadataFn::load(|name| {
+            std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        }));
+    }
+
+    if buffer_marker {
+        data.buffer_marker_ext = Some(vk::AmdBufferMarkerFn::load(|name| {
+            std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+        }));
+    }
+
+    data.queues = allocation.queues(&device, &properties);
+    tracing::debug!("queues: {:?}", data.queues);
+
+    data.dynamic_rendering = data
+        .features
+        .is_enabled(features::Feature::DynamicRendering);
+    if data.dynamic_rendering {
+        tracing::info!("using dynamic rendering");
 
-    device
+        if data.features.api_version < vk::API_VERSION_1_3 {
+            data.dynamic_rendering_ext = Some(ash::extensions::khr::DynamicRendering::new(
+                instance, &device,
+            ));
+        }
+    } else {
+        tracing::info!("using render passes");
+    }
+
+    data.synchronization2 = data
+        .features
+        .is_enabled(features::Feature::Synchronization2);
+    if data.synchronization2 && data.features.api_version < vk::API_VERSION_1_3 {
+        data.synchronization2_ext = Some(ash::extensions::khr::Synchronization2::new(
+            instance, &device,
+        ));
+    }
+
+    data.timeline_semaphore = data
+        .features
+        .is_enabled(features::Feature::TimelineSemaphore);
+    if data.timeline_semaphore && data.features.api_version < vk::API_VERSION_1_2 {
+        data.timeline_semaphore_ext = Some(ash::extensions::khr::TimelineSemaphore::new(
+            instance, &device,
+        ));
+    }
+
+    owned::DeviceOwner::new(instance, device)
+}
+
+unsafe fn create_timelines(device: &SharedDevice, data: &mut AppData) {
+    let queues = data.queues;
+    data.graphics_timeline = Rc::new(timeline::Timeline::new(
+        device,
+        data,
+        queues.graphics.handle,
+        "graphics timeline",
+    ));
+
+    // A queue shared with graphics shares its timeline, compute and transfer
+    // are never in the same family.
+    let timeline = |queue: queues::Queue, name| {
+        if queue.handle == queues.graphics.handle {
+            data.graphics_timeline.clone()
+        } else {
+            Rc::new(timeline::Timeline::new(device, data, queue.handle, name))
+        }
+    };
+    let compute_timeline = timeline(queues.compute, "compute timeline");
+    let transfer_timeline = timeline(queues.transfer, "transfer timeline");
+    data.compute_timeline = compute_timeline;
+    data.transfer_timeline = transfer_timeline;
+}
+
+/// Indices of the triangle of `shader.vert`.
+const INDICES: [u16; 3] = [0, 1, 2];
+
+unsafe fn create_index_buffer(instance: &Instance, device: &SharedDevice, data: &mut AppData) {
+    let contents = INDICES
+        .iter()
+        .flat_map(|i| i.to_ne_bytes())
+        .collect::<Vec<_>>();
+    let (index_buffer, staging) = upload::upload_buffer(
+        instance,
+        device,
+        data,
+        &contents,
+        vk::BufferUsageFlags::INDEX_BUFFER,
+        "index buffer",
+    );
+
+    // Every graphics submit drawing the scene waits for the upload, see
+    // [`index_buffer_dependency`], so the next one completing implies it has.
+    data.deletion_queue.retire(retire_value(data), staging);
+    data.index_buffer = Rc::new(index_buffer);
+}
+
+/// Returns the wait of a graphics submit drawing the scene for the upload of
+/// the index buffer, if it may not have completed.
+unsafe fn index_buffer_dependency(
+    device: &Device,
+    data: &AppData,
+) -> VkResult<Option<barrier::SemaphoreSubmit>> {
+    data.transfer_timeline.dependency(
+        device,
+        data,
+        data.index_buffer.value,
+        vk::PipelineStageFlags2::INDEX_INPUT,
+    )
 }
 
+const FEATURES: features::FeatureRequest = features::FeatureRequest {
+    required: &[features::Feature::GeometryShader],
+    optional: &[
+        features::Feature::FragmentStoresAndAtomics,
+        features::Feature::VertexPipelineStoresAndAtomics,
+        features::Feature::TimelineSemaphore,
+        features::Feature::Synchronization2,
+        features::Feature::DynamicRendering,
+    ],
+};
+
 const DEVICE_EXTENSIONS: &'static [&'static CStr] = &[&ash::extensions::khr::Swapchain::name()];
 
-unsafe fn check_physical_device_extensions(
+/// Number of frames recorded by the CPU while the GPU renders earlier ones.
+const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+unsafe fn missing_device_extensions(
     instance: &Instance,
     physical_device: vk::PhysicalDevice,
-) -> bool {
+) -> Vec<&'static CStr> {
     let extensions = instance
         .enumerate_device_extension_properties(physical_device)
         .unwrap()
@@ -530,16 +1540,18 @@ unsafe fn check_physical_device_extensions(
         .map(|e| e.extension_name)
         .collect::<HashSet<_>>();
 
-    if DEVICE_EXTENSIONS.iter().all(|e| {
-        let mut ext = [0; 256];
-        unsafe { std::ptr::copy_nonoverlapping(e.as_ptr(), ext.as_mut_ptr(), e.to_bytes().len()) };
-
-        extensions.contains(&ext)
-    }) {
-        true
-    } else {
-        false
-    }
+    DEVICE_EXTENSIONS
+        .iter()
+        .copied()
+        .filter(|e| {
+            let mut ext = [0; 256];
+            unsafe {
+                std::ptr::copy_nonoverlapping(e.as_ptr(), ext.as_mut_ptr(), e.to_bytes().len())
+            };
+
+            !extensions.contains(&ext)
+        })
+        .collect()
 }
 
 struct SwapchainSupport {
@@ -558,13 +1570,13 @@ impl SwapchainSupport {
         let ext = ash::extensions::khr::Surface::new(&entry, &instance);
 
         let capabilities = ext
-            .get_physical_device_surface_capabilities(physical_device, data.surface)
+            .get_physical_device_surface_capabilities(physical_device, *data.surface)
             .unwrap();
         let formats = ext
-            .get_physical_device_surface_formats(physical_device, data.surface)
+            .get_physical_device_surface_formats(physical_device, *data.surface)
             .unwrap();
         let present_modes = ext
-            .get_physical_device_surface_present_modes(physical_device, data.surface)
+            .get_physical_device_surface_present_modes(physical_device, *data.surface)
             .unwrap();
 
         Self {
@@ -575,25 +1587,6 @@ impl SwapchainSupport {
     }
 }
 
-fn get_swapchain_surface_format(formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
-    formats
-        .iter()
-        .cloned()
-        .find(|f| {
-            f.format == vk::Format::B8G8R8A8_SRGB
-                && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        })
-        .unwrap_or_else(|| formats[0])
-}
-
-fn get_swapchain_present_modes(present_modes: &[vk::PresentModeKHR]) -> vk::PresentModeKHR {
-    present_modes
-        .iter()
-        .cloned()
-        .find(|m| *m == vk::PresentModeKHR::MAILBOX)
-        .unwrap_or(vk::PresentModeKHR::FIFO)
-}
-
 fn get_swapchain_extent(window: &Window, capabilities: vk::SurfaceCapabilitiesKHR) -> vk::Extent2D {
     if capabilities.current_extent.width != u32::MAX {
         capabilities.current_extent
@@ -614,18 +1607,27 @@ fn get_swapchain_extent(window: &Window, capabilities: vk::SurfaceCapabilitiesKH
     }
 }
 
+/// `old_swapchain` is the swapchain being replaced, or null.
 unsafe fn create_swapchain(
     entry: &Entry,
     window: &Window,
     instance: &Instance,
-    device: &Device,
+    device: &SharedDevice,
     data: &mut AppData,
+    old_swapchain: vk::SwapchainKHR,
 ) {
-    let indices = QueueFamilyIndices::get(entry, instance, data, data.physical_device).unwrap();
     let support = SwapchainSupport::get(entry, instance, data, data.physical_device);
 
-    let surface_format = get_swapchain_surface_format(&support.formats);
-    let present_modes = get_swapchain_present_modes(&support.present_modes);
+    let surface_format = surface_format::select(data.config.surface_format, &support.formats);
+    let output_transfer = surface_format::output_transfer(surface_format);
+    tracing::info!(
+        "using surface format {:?} {:?}, output transfer {:?}",
+        surface_format.format,
+        surface_format.color_space,
+        output_transfer
+    );
+    let present_mode = present::select(&data.present_modes, &support.present_modes);
+    tracing::info!("using present mode {:?}", present_mode);
     let extent = get_swapchain_extent(window, support.capabilities);
 
     let mut image_count = support.capabilities.min_image_count + 1;
@@ -635,44 +1637,71 @@ unsafe fn create_swapchain(
         image_count = support.capabilities.max_image_count;
     }
 
+    // TRANSFER_SRC allows reading back presented frames for screenshots.
+    let mut image_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+    if support
+        .capabilities
+        .supported_usage_flags
+        .contains(vk::ImageUsageFlags::TRANSFER_SRC)
+    {
+        image_usage |= vk::ImageUsageFlags::TRANSFER_SRC;
+    }
+
     let mut queue_family_indices = vec![];
-    let image_sharing_mode = if indices.graphics != indices.present {
-        queue_family_indices.push(indices.graphics);
-        queue_family_indices.push(indices.present);
+    let graphics = data.queues.graphics.family;
+    let present = data.queues.present.family;
+    let image_sharing_mode = if graphics != present {
+        queue_family_indices.push(graphics);
+        queue_family_indices.push(present);
         vk::SharingMode::CONCURRENT
     } else {
         vk::SharingMode::EXCLUSIVE
     };
 
     let info = vk::SwapchainCreateInfoKHR::builder()
-        .surface(data.surface)
+        .surface(*data.surface)
         .min_image_count(image_count)
         .image_format(surface_format.format)
         .image_color_space(surface_format.color_space)
         .image_extent(extent)
         .image_array_layers(1)
-        .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT)
+        .image_usage(image_usage)
         .image_sharing_mode(image_sharing_mode)
         .queue_family_indices(&queue_family_indices)
         .pre_transform(support.capabilities.current_transform)
         .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-        .present_mode(present_modes)
+        .present_mode(present_mode)
         .clipped(true)
-        .old_swapchain(vk::SwapchainKHR::null());
+        .old_swapchain(old_swapchain);
 
-    data.swapchain = ash::extensions::khr::Swapchain::new(instance, device)
-        .create_swapchain(&info, None)
-        .unwrap();
+    let swapchain = ash::extensions::khr::Swapchain::new(instance, device);
+    data.swapchain = Owned::new(device, swapchain.create_swapchain(&info, None).unwrap());
+    data.swapchain_images = swapchain.get_swapchain_images(*data.swapchain).unwrap();
 
-    data.swapchain_images = ash::extensions::khr::Swapchain::new(&instance, device)
-        .get_swapchain_images(data.swapchain)
-        .unwrap();
+    debug::set_object_name(device, data, *data.swapchain, "swapchain");
+    for (i, image) in data.swapchain_images.iter().enumerate() {
+        debug::set_object_name(device, data, *image, &format!("swapchain image {}", i));
+    }
+
+    if surface_format.color_space == vk::ColorSpaceKHR::HDR10_ST2084_EXT {
+        match &data.hdr_metadata_ext {
+            Some(ext) => {
+                let metadata = surface_format::hdr10_metadata();
+                (ext.set_hdr_metadata_ext)(device.handle(), 1, &*data.swapchain, &metadata);
+            }
+            None => tracing::info!("VK_EXT_hdr_metadata not supported"),
+        }
+    }
 
     data.swapchain_format = surface_format.format;
+    data.swapchain_color_space = surface_format.color_space;
+    data.output_transfer = output_transfer;
     data.swapchain_extent = extent;
+    data.swapchain_usage = image_usage;
+    data.present_mode = present_mode;
 }
 
-unsafe fn create_swapchain_image_views(device: &Device, data: &mut AppData) {
+unsafe fn create_swapchain_image_views(device: &SharedDevice, data: &mut AppData) {
     let components = vk::ComponentMapping::builder()
         .r(vk::ComponentSwizzle::IDENTITY)
         .g(vk::ComponentSwizzle::IDENTITY)
@@ -697,27 +1726,50 @@ unsafe fn create_swapchain_image_views(device: &Device, data: &mut AppData) {
                 .components(*components)
                 .subresource_range(*subresource_range);
 
-            device.create_image_view(&info, None).unwrap()
+            Owned::new(device, device.create_image_view(&info, None).unwrap())
         })
         .collect::<Vec<_>>();
+
+    for (i, view) in data.swapchain_image_view.iter().enumerate() {
+        debug::set_object_name(device, data, **view, &format!("swapchain image view {}", i));
+    }
 }
 
-unsafe fn create_pipeline(device: &Device, data: &mut AppData) {
-    let vert = include_bytes!("../vert.spv");
-    let frag = include_bytes!("../frag.spv");
+unsafe fn create_pipeline(device: &SharedDevice, data: &mut AppData) {
+    let load = |path: &Option<std::path::PathBuf>, built_in: &[u8]| match path {
+        Some(path) => std::fs::read(path)
+            .unwrap_or_else(|err| panic!("failed to read {}: {}", path.display(), err)),
+        None => built_in.to_vec(),
+    };
+    let vert = load(&data.config.vertex_shader, include_bytes!("../vert.spv"));
+    let frag = load(&data.config.fragment_shader, include_bytes!("../frag.spv"));
 
-    let vert_shader = create_shader_module(device, &vert[..]);
-    let frag_shader = create_shader_module(device, &frag[..]);
+    let vert_shader = create_shader_module(device, &vert);
+    let frag_shader = create_shader_module(device, &frag);
+    debug::set_object_name(device, data, *vert_shader, "shader.vert");
+    debug::set_object_name(device, data, *frag_shader, "shader.frag");
 
     let vert_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::VERTEX)
-        .module(vert_shader)
+        .module(*vert_shader)
         .name(CStr::from_bytes_with_nul(b"main\0").unwrap());
 
+    // OUTPUT_TRANSFER in shader.frag.
+    let output_transfer = (data.output_transfer as u32).to_ne_bytes();
+    let map_entries = &[vk::SpecializationMapEntry {
+        constant_id: 0,
+        offset: 0,
+        size: std::mem::size_of::<u32>(),
+    }];
+    let specialization_info = vk::SpecializationInfo::builder()
+        .map_entries(map_entries)
+        .data(&output_transfer);
+
     let frag_stage = vk::PipelineShaderStageCreateInfo::builder()
         .stage(vk::ShaderStageFlags::FRAGMENT)
-        .module(frag_shader)
-        .name(CStr::from_bytes_with_nul(b"main\0").unwrap());
+        .module(*frag_shader)
+        .name(CStr::from_bytes_with_nul(b"main\0").unwrap())
+        .specialization_info(&specialization_info);
 
     let vertex_input_state = vk::PipelineVertexInputStateCreateInfo::builder();
 
@@ -783,11 +1835,19 @@ unsafe fn create_pipeline(device: &Device, data: &mut AppData) {
         vk::PipelineDynamicStateCreateInfo::builder().dynamic_states(dynamic_states);
 
     let layout_info = vk::PipelineLayoutCreateInfo::builder();
-    data.pipeline_layout = device.create_pipeline_layout(&layout_info, None).unwrap();
+    data.pipeline_layout = Owned::new(
+        device,
+        device.create_pipeline_layout(&layout_info, None).unwrap(),
+    );
+    debug::set_object_name(device, data, *data.pipeline_layout, "main pipeline layout");
 
     let stages = &[vert_stage.build(), frag_stage.build()];
 
-    let info = vk::GraphicsPipelineCreateInfo::builder()
+    let color_attachment_formats = &[data.swapchain_format];
+    let mut rendering_info = vk::PipelineRenderingCreateInfo::builder()
+        .color_attachment_formats(color_attachment_formats);
+
+    let mut info = vk::GraphicsPipelineCreateInfo::builder()
         .stages(stages)
         .vertex_input_state(&vertex_input_state)
         .input_assembly_state(&input_assembly_state)
@@ -795,20 +1855,22 @@ unsafe fn create_pipeline(device: &Device, data: &mut AppData) {
         .rasterization_state(&rasterization_state)
         .multisample_state(&multisample_state)
         .color_blend_state(&color_blend_state)
-        .layout(data.pipeline_layout)
-        .render_pass(data.render_pass)
-        .subpass(0)
-        .build();
+        .layout(*data.pipeline_layout);
 
-    data.pipeline = device
-        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)
-        .unwrap()[0];
+    if data.dynamic_rendering {
+        info = info.push_next(&mut rendering_info);
+    } else {
+        info = info.render_pass(*data.render_pass).subpass(0);
+    }
 
-    device.destroy_shader_module(vert_shader, None);
-    device.destroy_shader_module(frag_shader, None);
+    let pipeline = device
+        .create_graphics_pipelines(vk::PipelineCache::null(), &[info.build()], None)
+        .unwrap()[0];
+    data.pipeline = Owned::new(device, pipeline);
+    debug::set_object_name(device, data, *data.pipeline, "main pipeline");
 }
 
-unsafe fn create_shader_module(device: &Device, buf: &[u8]) -> vk::ShaderModule {
+unsafe fn create_shader_module(device: &SharedDevice, buf: &[u8]) -> Owned<vk::ShaderModule> {
     let buf = buf.to_vec();
 
     let (prefix, code, suffix) = buf.align_to::<u32>();
@@ -819,10 +1881,10 @@ unsafe fn create_shader_module(device: &Device, buf: &[u8]) -> vk::ShaderModule
 
     let info = vk::ShaderModuleCreateInfo::builder().code(code);
 
-    device.create_shader_module(&info, None).unwrap()
+    Owned::new(device, device.create_shader_module(&info, None).unwrap())
 }
 
-unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mut AppData) {
+unsafe fn create_render_pass(instance: &Instance, device: &SharedDevice, data: &mut AppData) {
     let color_attachment = vk::AttachmentDescription::builder()
         .format(data.swapchain_format)
         .samples(vk::SampleCountFlags::TYPE_1)
@@ -830,8 +1892,8 @@ unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mut Ap
         .store_op(vk::AttachmentStoreOp::STORE)
         .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
         .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-        .initial_layout(vk::ImageLayout::UNDEFINED)
-        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+        .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
         .build();
 
     let color_attachment_ref = vk::AttachmentReference::builder()
@@ -862,51 +1924,69 @@ unsafe fn create_render_pass(instance: &Instance, device: &Device, data: &mut Ap
         .subpasses(subpasses)
         .dependencies(dependencies);
 
-    data.render_pass = device.create_render_pass(&info, None).unwrap();
+    data.render_pass = Owned::new(device, device.create_render_pass(&info, None).unwrap());
+    debug::set_object_name(device, data, *data.render_pass, "main render pass");
 }
 
-unsafe fn create_framebuffers(device: &Device, data: &mut AppData) {
+unsafe fn create_framebuffers(device: &SharedDevice, data: &mut AppData) {
     data.framebuffers = data
         .swapchain_image_view
         .iter()
         .map(|i| {
-            let attachments = &[*i];
+            let attachments = &[**i];
 
             let create_info = vk::FramebufferCreateInfo::builder()
-                .render_pass(data.render_pass)
+                .render_pass(*data.render_pass)
                 .attachments(attachments)
                 .width(data.swapchain_extent.width)
                 .height(data.swapchain_extent.height)
                 .layers(1);
 
-            device.create_framebuffer(&create_info, None).unwrap()
+            Owned::new(
+                device,
+                device.create_framebuffer(&create_info, None).unwrap(),
+            )
         })
         .collect::<Vec<_>>();
-}
 
-unsafe fn create_command_pool(
-    entry: &Entry,
-    instance: &Instance,
-    device: &Device,
-    data: &mut AppData,
-) {
-    let indices = QueueFamilyIndices::get(entry, instance, data, data.physical_device).unwrap();
+    for (i, framebuffer) in data.framebuffers.iter().enumerate() {
+        debug::set_object_name(device, data, **framebuffer, &format!("framebuffer {}", i));
+    }
+}
 
+unsafe fn create_command_pool(device: &SharedDevice, data: &mut AppData) {
     let info = vk::CommandPoolCreateInfo::builder()
         .flags(vk::CommandPoolCreateFlags::empty())
-        .queue_family_index(indices.graphics);
+        .queue_family_index(data.queues.graphics.family);
 
-    data.command_pool = device.create_command_pool(&info, None).unwrap();
+    data.command_pool = Owned::new(device, device.create_command_pool(&info, None).unwrap());
+    debug::set_object_name(device, data, *data.command_pool, "graphics command pool");
 }
 
-unsafe fn create_command_buffers(device: &Device, data: &mut AppData) {
+unsafe fn create_gpu_timer(instance: &Instance, device: &SharedDevice, data: &mut AppData) {
+    data.gpu_timer = timing::GpuTimer::new(instance, device, data, data.swapchain_images.len());
+}
+
+unsafe fn create_command_buffers(instance: &Instance, device: &SharedDevice, data: &mut AppData) {
     let allocate_info = vk::CommandBufferAllocateInfo::builder()
-        .command_pool(data.command_pool)
+        .command_pool(*data.command_pool)
         .level(vk::CommandBufferLevel::PRIMARY)
-        .command_buffer_count(data.framebuffers.len() as u32);
+        .command_buffer_count(data.swapchain_images.len() as u32);
 
     data.command_buffers = device.allocate_command_buffers(&allocate_info).unwrap();
 
+    for (i, command_buffer) in data.command_buffers.iter().enumerate() {
+        debug::set_object_name(device, data, *command_buffer, &format!("frame {}", i));
+    }
+
+    data.breadcrumbs = Some(breadcrumbs::Breadcrumbs::new(
+        instance,
+        device,
+        data,
+        data.command_buffers.len(),
+    ));
+
+    let mut transients = Vec::new();
     for (i, command_buffer) in data.command_buffers.iter().enumerate() {
         let inheritance = vk::CommandBufferInheritanceInfo::builder();
 
@@ -915,43 +1995,179 @@ unsafe fn create_command_buffers(device: &Device, data: &mut AppData) {
             .inheritance_info(&inheritance);
 
         device.begin_command_buffer(*command_buffer, &info).unwrap();
+        debug::begin_label(data, *command_buffer, "frame");
+
+        let frame_scope = data.gpu_timer.as_ref().map(|timer| {
+            timer.reset(device, *command_buffer, i);
+            timer.begin(device, *command_buffer, i, "frame")
+        });
+
+        // The previous contents of the image are discarded every frame.
+        let mut graph = graph::RenderGraph::new();
+        graph.breadcrumbs(i);
+        graph.timestamps(i);
+        let target = graph.import_image(
+            "swapchain image",
+            data.swapchain_images[i],
+            *data.swapchain_image_view[i],
+            data.swapchain_extent,
+            state::Access::ACQUIRED,
+        );
+        graph.output(target, state::Access::PRESENT);
 
-        let render_area = vk::Rect2D::builder()
-            .offset(vk::Offset2D::default())
-            .extent(data.swapchain_extent)
-            .build();
+        let framebuffer = data.framebuffers.get(i).map(|f| **f).unwrap_or_default();
+        add_scene_pass(&mut graph, target, *data.render_pass, framebuffer);
 
-        let color_clear_value = vk::ClearValue {
-            color: vk::ClearColorValue {
-                float32: [0.0, 0.0, 0.0, 1.0],
-            },
-        };
+        transients.push(graph.execute(instance, device, data, *command_buffer));
 
-        let clear_values = &[color_clear_value];
-        let info = vk::RenderPassBeginInfo::builder()
-            .render_pass(data.render_pass)
-            .framebuffer(data.framebuffers[i])
-            .render_area(render_area)
-            .clear_values(clear_values);
+        if let Some((timer, scope)) = data.gpu_timer.as_ref().zip(frame_scope) {
+            timer.end(device, *command_buffer, scope);
+        }
 
-        device.cmd_begin_render_pass(*command_buffer, &info, vk::SubpassContents::INLINE);
+        debug::end_label(data, *command_buffer);
+        device.end_command_buffer(*command_buffer).unwrap();
+    }
 
-        device.cmd_bind_pipeline(
-            *command_buffer,
-            vk::PipelineBindPoint::GRAPHICS,
-            data.pipeline,
-        );
+    if let Some(transients) = transients.first() {
+        graph_export::dump_if_requested(transients.description());
+    }
 
-        device.cmd_draw(*command_buffer, 3, 1, 0, 0);
+    data.graph_transients = transients;
+}
 
-        device.cmd_end_render_pass(*command_buffer);
-        device.end_command_buffer(*command_buffer).unwrap();
-    }
+/// Adds a pass clearing `target` and drawing the scene into it.
+///
+/// Without dynamic rendering, `render_pass` and `framebuffer` are used, which
+/// must be compatible with `data.render_pass` and render to `target`. The
+/// framebuffer of a transient `target` is created by the graph, see
+/// [`graph::ImageDesc::render_pass`].
+fn add_scene_pass(
+    graph: &mut graph::RenderGraph,
+    target: graph::ResourceId,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+) {
+    graph
+        .pass("main pass")
+        .overwrite(target, state::Access::COLOR_ATTACHMENT)
+        .execute(move |ctx| unsafe {
+            let (device, data, command_buffer) = (ctx.device, ctx.data, ctx.command_buffer);
+            let extent = ctx.extent(target);
+            let framebuffer = ctx.framebuffer(target).unwrap_or(framebuffer);
+
+            if data.dynamic_rendering {
+                rendering::begin_rendering(device, data, command_buffer, ctx.view(target), extent);
+                record_scene(device, data, command_buffer);
+                rendering::end_rendering(device, data, command_buffer);
+                return;
+            }
+
+            let render_area = vk::Rect2D::builder()
+                .offset(vk::Offset2D::default())
+                .extent(extent)
+                .build();
+
+            let color_clear_value = vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            };
+
+            let clear_values = &[color_clear_value];
+            let info = vk::RenderPassBeginInfo::builder()
+                .render_pass(render_pass)
+                .framebuffer(framebuffer)
+                .render_area(render_area)
+                .clear_values(clear_values);
+
+            device.cmd_begin_render_pass(command_buffer, &info, vk::SubpassContents::INLINE);
+            record_scene(device, data, command_buffer);
+            device.cmd_end_render_pass(command_buffer);
+        });
 }
 
-unsafe fn create_sync_objects(device: &Device, data: &mut AppData) {
+/// Records the draw commands of the scene into a command buffer inside a
+/// render pass compatible with `data.render_pass`, or inside dynamic rendering
+/// to a `data.swapchain_format` attachment.
+unsafe fn record_scene(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+    device.cmd_bind_pipeline(
+        command_buffer,
+        vk::PipelineBindPoint::GRAPHICS,
+        *data.pipeline,
+    );
+
+    device.cmd_bind_index_buffer(
+        command_buffer,
+        *data.index_buffer.buffer,
+        0,
+        vk::IndexType::UINT16,
+    );
+
+    let (instance_count, first_instance) = data.config.scene.instances();
+    device.cmd_draw_indexed(
+        command_buffer,
+        INDICES.len() as u32,
+        instance_count,
+        0,
+        0,
+        first_instance,
+    );
+}
+
+unsafe fn begin_single_time_commands(device: &Device, data: &AppData) -> vk::CommandBuffer {
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(*data.command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+
+    let command_buffer = device.allocate_command_buffers(&info).unwrap()[0];
+
+    let info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+
+    device.begin_command_buffer(command_buffer, &info).unwrap();
+
+    command_buffer
+}
+
+/// Submits a command buffer from [`begin_single_time_commands`] after `wait`
+/// and waits for it to complete.
+unsafe fn end_single_time_commands(
+    device: &SharedDevice,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    wait: &[barrier::SemaphoreSubmit],
+) {
+    device.end_command_buffer(command_buffer).unwrap();
+
+    let command_buffers = &[command_buffer];
+    let value = data
+        .graphics_timeline
+        .submit(device, data, wait, command_buffers, &[])
+        .unwrap();
+    data.debug_state.check();
+    data.graphics_timeline.wait(device, data, value).unwrap();
+
+    device.free_command_buffers(*data.command_pool, command_buffers);
+}
+
+unsafe fn create_sync_objects(device: &SharedDevice, data: &mut AppData) {
     let info = vk::SemaphoreCreateInfo::builder();
 
-    data.image_available_semaphore = device.create_semaphore(&info, None).unwrap();
-    data.render_finished_semaphore = device.create_semaphore(&info, None).unwrap();
+    data.image_available_semaphores = (0..MAX_FRAMES_IN_FLIGHT)
+        .map(|_| Owned::new(device, device.create_semaphore(&info, None).unwrap()))
+        .collect();
+    data.render_finished_semaphores = (0..data.swapchain_images.len())
+        .map(|_| Owned::new(device, device.create_semaphore(&info, None).unwrap()))
+        .collect();
+
+    for (i, semaphore) in data.image_available_semaphores.iter().enumerate() {
+        debug::set_object_name(device, data, **semaphore, &format!("image available {}", i));
+    }
+    for (i, semaphore) in data.render_finished_semaphores.iter().enumerate() {
+        debug::set_object_name(device, data, **semaphore, &format!("render finished {}", i));
+    }
+
+    data.frame_values = vec![0; MAX_FRAMES_IN_FLIGHT];
+    data.image_values = vec![0; data.swapchain_images.len()];
 }
@@ -0,0 +1,65 @@
+use ash::vk;
+use ash::Instance;
+
+use crate::owned::{Owned, SharedDevice};
+use crate::AppData;
+
+pub unsafe fn find_memory_type(
+    instance: &Instance,
+    data: &AppData,
+    type_bits: u32,
+    properties: vk::MemoryPropertyFlags,
+) -> u32 {
+    let memory = instance.get_physical_device_memory_properties(data.physical_device);
+
+    (0..memory.memory_type_count)
+        .find(|i| {
+            type_bits & (1 << i) != 0
+                && memory.memory_types[*i as usize]
+                    .property_flags
+                    .contains(properties)
+        })
+        .expect("no suitable memory type")
+}
+
+/// Creates a buffer bound to new memory. The buffer is shared by the
+/// `queue_families` if they differ, and owned by one family otherwise.
+pub unsafe fn create_buffer(
+    instance: &Instance,
+    device: &SharedDevice,
+    data: &AppData,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+    properties: vk::MemoryPropertyFlags,
+    queue_families: &[u32],
+) -> (Owned<vk::Buffer>, Owned<vk::DeviceMemory>) {
+    let mut families = queue_families.to_vec();
+    families.sort_unstable();
+    families.dedup();
+
+    let info = vk::BufferCreateInfo::builder().size(size).usage(usage);
+    let info = if families.len() > 1 {
+        info.sharing_mode(vk::SharingMode::CONCURRENT)
+            .queue_family_indices(&families)
+    } else {
+        info.sharing_mode(vk::SharingMode::EXCLUSIVE)
+    };
+
+    let buffer = Owned::new(device, device.create_buffer(&info, None).unwrap());
+
+    let requirements = device.get_buffer_memory_requirements(*buffer);
+
+    let info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(requirements.size)
+        .memory_type_index(find_memory_type(
+            instance,
+            data,
+            requirements.memory_type_bits,
+            properties,
+        ));
+
+    let memory = Owned::new(device, device.allocate_memory(&info, None).unwrap());
+    device.bind_buffer_memory(*buffer, *memory, 0).unwrap();
+
+    (buffer, memory)
+}
@@ -0,0 +1,113 @@
+//! Rendering into an image that is not owned by the swapchain.
+//!
+//! Used when the swapchain images cannot be read back directly, i.e. when the
+//! surface does not support `TRANSFER_SRC` usage, and for headless rendering.
+
+use ash::vk;
+use ash::Instance;
+
+use crate::debug;
+use crate::graph::{ImageDesc, RenderGraph};
+use crate::graph_export;
+use crate::owned::{Owned, SharedDevice};
+use crate::screenshot::{self, Readback, Screenshot};
+use crate::state::Access;
+use crate::{
+    add_scene_pass, begin_single_time_commands, end_single_time_commands, index_buffer_dependency,
+    AppData,
+};
+
+/// Renders the scene with the swapchain format and extent into a transient
+/// image of a render graph, reads it back and waits for completion.
+pub unsafe fn render(instance: &Instance, device: &SharedDevice, data: &AppData) -> Screenshot {
+    let format = vk::SurfaceFormatKHR {
+        format: data.swapchain_format,
+        color_space: data.swapchain_color_space,
+    };
+    let extent = data.swapchain_extent;
+
+    let render_pass = if data.dynamic_rendering {
+        Owned::default()
+    } else {
+        create_render_pass(device, data, format.format)
+    };
+    let readback = Readback::new(instance, device, data, format, extent);
+
+    let command_buffer = begin_single_time_commands(device, data);
+    debug::begin_label(data, command_buffer, "offscreen");
+
+    let mut graph = RenderGraph::new();
+    let target = graph.create_image(
+        "offscreen image",
+        ImageDesc {
+            format: format.format,
+            extent,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            render_pass: *render_pass,
+        },
+    );
+    let buffer = graph.import_buffer("screenshot readback", readback.buffer(), Access::UNDEFINED);
+    graph.output(buffer, Access::HOST_READ);
+
+    add_scene_pass(&mut graph, target, *render_pass, vk::Framebuffer::null());
+
+    graph
+        .pass("readback")
+        .read(target, Access::TRANSFER_READ)
+        .write(buffer, Access::TRANSFER_WRITE)
+        .execute(move |ctx| unsafe {
+            let (image, buffer) = (ctx.image(target), ctx.buffer(buffer));
+            screenshot::record_copy(ctx.device, ctx.command_buffer, image, buffer, extent);
+        });
+
+    let transients = graph.execute(instance, device, data, command_buffer);
+    graph_export::dump_if_requested(transients.description());
+
+    debug::end_label(data, command_buffer);
+    let wait = index_buffer_dependency(device, data).unwrap();
+    end_single_time_commands(device, data, command_buffer, wait.as_slice());
+
+    readback.read(device)
+}
+
+unsafe fn create_render_pass(
+    device: &SharedDevice,
+    data: &AppData,
+    format: vk::Format,
+) -> Owned<vk::RenderPass> {
+    // Compatible with the swapchain render pass, so the same pipeline can
+    // be used.
+    let color_attachment = vk::AttachmentDescription::builder()
+        .format(format)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+        .initial_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .final_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .build();
+
+    let color_attachment_ref = vk::AttachmentReference::builder()
+        .attachment(0)
+        .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .build();
+
+    let color_attachments = &[color_attachment_ref];
+    let subpass = vk::SubpassDescription::builder()
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+        .color_attachments(color_attachments)
+        .build();
+
+    let attachments = &[color_attachment];
+    let subpasses = &[subpass];
+
+    let info = vk::RenderPassCreateInfo::builder()
+        .attachments(attachments)
+        .subpasses(subpasses);
+
+    let render_pass = Owned::new(device, device.create_render_pass(&info, None).unwrap());
+    debug::set_object_name(device, data, *render_pass, "offscreen render pass");
+
+    render_pass
+}
@@ -0,0 +1,244 @@
+//! Ownership of Vulkan objects.
+//!
+//! Objects are destroyed when their owner is dropped. Every object created
+//! from the device holds a [`SharedDevice`], which holds the
+//! [`SharedInstance`], so the device is destroyed after the last object
+//! created from it and the instance after the device, whatever order the
+//! owners are dropped in.
+//!
+//! Objects that depend on other objects than the device (e.g. image views on
+//! swapchain images, a swapchain on its surface) are declared after them in
+//! their owning struct, fields are dropped in declaration order.
+
+use std::ops::Deref;
+use std::rc::Rc;
+
+use ash::extensions::ext::DebugUtils;
+use ash::extensions::khr;
+use ash::vk::{self, Handle};
+use ash::{Device, Entry, Instance};
+
+use crate::debug::DebugState;
+use crate::registry::ObjectRegistry;
+
+pub type SharedInstance = Rc<InstanceOwner>;
+pub type SharedDevice = Rc<DeviceOwner>;
+
+/// The instance and its debug messenger.
+pub struct InstanceOwner {
+    entry: Entry,
+    instance: Instance,
+    messenger: Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>,
+    /// Referenced by the debug messenger, must outlive it.
+    debug_state: Rc<DebugState>,
+}
+
+impl InstanceOwner {
+    pub fn new(
+        entry: Entry,
+        instance: Instance,
+        messenger: Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>,
+        debug_state: Rc<DebugState>,
+    ) -> SharedInstance {
+        Rc::new(Self {
+            entry,
+            instance,
+            messenger,
+            debug_state,
+        })
+    }
+
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+}
+
+impl Deref for InstanceOwner {
+    type Target = Instance;
+
+    fn deref(&self) -> &Instance {
+        &self.instance
+    }
+}
+
+impl Drop for InstanceOwner {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some((debug_utils, messenger)) = &self.messenger {
+                debug_utils.destroy_debug_utils_messenger(*messenger, None);
+            }
+
+            self.instance.destroy_instance(None);
+        }
+
+        let errors = self.debug_state.errors();
+        if errors != 0 {
+            tracing::error!("{} validation errors reported", errors);
+        }
+    }
+}
+
+/// The logical device, keeping the instance alive.
+pub struct DeviceOwner {
+    device: Device,
+    registry: ObjectRegistry,
+    instance: SharedInstance,
+}
+
+impl DeviceOwner {
+    pub fn new(instance: &SharedInstance, device: Device) -> SharedDevice {
+        Rc::new(Self {
+            device,
+            registry: ObjectRegistry::default(),
+            instance: instance.clone(),
+        })
+    }
+
+    /// The objects created from the device that are still alive.
+    pub fn registry(&self) -> &ObjectRegistry {
+        &self.registry
+    }
+}
+
+impl Deref for DeviceOwner {
+    type Target = Device;
+
+    fn deref(&self) -> &Device {
+        &self.device
+    }
+}
+
+impl Drop for DeviceOwner {
+    fn drop(&mut self) {
+        unsafe {
+            // Nothing may be in use anymore, but this is also reached when
+            // unwinding from a panic while frames are in flight.
+            let _ = self.device.device_wait_idle();
+            self.device.destroy_device(None);
+        }
+    }
+}
+
+/// A handle that can be destroyed with the device it was created from.
+pub trait Destroy: Handle + Copy + Default + PartialEq {
+    unsafe fn destroy(self, device: &DeviceOwner);
+}
+
+macro_rules! impl_destroy {
+    ($($type:ty => $method:ident,)*) => {
+        $(
+            impl Destroy for $type {
+                unsafe fn destroy(self, device: &DeviceOwner) {
+                    device.$method(self, None);
+                }
+            }
+        )*
+    };
+}
+
+impl_destroy! {
+    vk::Buffer => destroy_buffer,
+    vk::CommandPool => destroy_command_pool,
+    vk::DeviceMemory => free_memory,
+    vk::Fence => destroy_fence,
+    vk::Framebuffer => destroy_framebuffer,
+    vk::Image => destroy_image,
+    vk::ImageView => destroy_image_view,
+    vk::Pipeline => destroy_pipeline,
+    vk::PipelineLayout => destroy_pipeline_layout,
+    vk::QueryPool => destroy_query_pool,
+    vk::RenderPass => destroy_render_pass,
+    vk::Semaphore => destroy_semaphore,
+    vk::ShaderModule => destroy_shader_module,
+}
+
+impl Destroy for vk::SwapchainKHR {
+    unsafe fn destroy(self, device: &DeviceOwner) {
+        khr::Swapchain::new(&device.instance, &device.device).destroy_swapchain(self, None);
+    }
+}
+
+/// An object created from a device, destroyed on drop.
+///
+/// The default value is a null handle without a device, which is not
+/// destroyed.
+pub struct Owned<T: Destroy> {
+    handle: T,
+    device: Option<SharedDevice>,
+}
+
+impl<T: Destroy> Owned<T> {
+    pub fn new(device: &SharedDevice, handle: T) -> Self {
+        if handle != T::default() {
+            device.registry.register(T::TYPE, handle.as_raw());
+        }
+
+        Self {
+            handle,
+            device: Some(device.clone()),
+        }
+    }
+}
+
+impl<T: Destroy> Default for Owned<T> {
+    fn default() -> Self {
+        Self {
+            handle: T::default(),
+            device: None,
+        }
+    }
+}
+
+impl<T: Destroy> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.handle
+    }
+}
+
+impl<T: Destroy> Drop for Owned<T> {
+    fn drop(&mut self) {
+        if let Some(device) = &self.device {
+            if self.handle != T::default() {
+                unsafe { self.handle.destroy(device) };
+                device.registry.unregister(T::TYPE, self.handle.as_raw());
+            }
+        }
+    }
+}
+
+/// A surface, keeping the instance alive.
+#[derive(Default)]
+pub struct Surface {
+    handle: vk::SurfaceKHR,
+    instance: Option<SharedInstance>,
+}
+
+impl Surface {
+    pub fn new(instance: &SharedInstance, handle: vk::SurfaceKHR) -> Self {
+        Self {
+            handle,
+            instance: Some(instance.clone()),
+        }
+    }
+}
+
+impl Deref for Surface {
+    type Target = vk::SurfaceKHR;
+
+    fn deref(&self) -> &vk::SurfaceKHR {
+        &self.handle
+    }
+}
+
+impl Drop for Surface {
+    fn drop(&mut self) {
+        if let Some(instance) = &self.instance {
+            unsafe {
+                khr::Surface::new(&instance.entry, &instance.instance)
+                    .destroy_surface(self.handle, None);
+            }
+        }
+    }
+}
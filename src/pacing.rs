@@ -0,0 +1,300 @@
+//! Frame pacing.
+//!
+//! [`FramePacer`] decides when the event loop renders. In `continuous` mode a
+//! frame is rendered as soon as the previous one is submitted, limited by the
+//! optional FPS cap. In `on-demand` mode frames are only rendered when the
+//! window needs to be redrawn: when it is exposed or resized, or on input.
+//! Rendering is paused while the window is minimized or occluded.
+//!
+//! The mode and the cap are configured by the `pacing.redraw` and
+//! `pacing.max_fps` settings, see [`config`](crate::config). Frame time
+//! statistics are logged periodically.
+//!
+//! Every window has its own pacer, the event loop waits for the earliest frame
+//! of any window, see [`earliest`].
+
+use std::time::{Duration, Instant};
+
+use winit::event::WindowEvent;
+use winit::event_loop::ControlFlow;
+use winit::window::Window;
+
+/// Interval between logged frame time statistics.
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RedrawMode {
+    /// Render frames continuously.
+    #[default]
+    Continuous,
+    /// Render frames only when the window needs to be redrawn.
+    OnDemand,
+}
+
+impl RedrawMode {
+    /// Parses `continuous` or `on-demand`.
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "continuous" => Some(Self::Continuous),
+            "on-demand" => Some(Self::OnDemand),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PacingConfig {
+    pub redraw: RedrawMode,
+    /// Minimum time between frames in continuous mode.
+    pub frame_interval: Option<Duration>,
+}
+
+pub struct FramePacer {
+    config: PacingConfig,
+    /// Earliest start of the next frame with an FPS cap.
+    next_frame: Instant,
+    minimized: bool,
+    occluded: bool,
+    stats: FrameStats,
+}
+
+impl FramePacer {
+    pub fn new(config: PacingConfig) -> Self {
+        tracing::info!("frame pacing: {:?}", config);
+
+        Self {
+            config,
+            next_frame: Instant::now(),
+            minimized: false,
+            occluded: false,
+            stats: FrameStats::default(),
+        }
+    }
+
+    /// Returns whether rendering is paused because the window is not visible.
+    pub fn paused(&self) -> bool {
+        self.minimized || self.occluded
+    }
+
+    /// Tracks the visibility of the window and requests redraws for events
+    /// that may change its contents.
+    pub fn window_event(&mut self, window: &Window, event: &WindowEvent) {
+        let paused = self.paused();
+
+        match event {
+            WindowEvent::Resized(size) => {
+                self.minimized = size.width == 0 || size.height == 0;
+                window.request_redraw();
+            }
+            WindowEvent::Occluded(occluded) => self.occluded = *occluded,
+            WindowEvent::KeyboardInput { .. }
+            | WindowEvent::MouseInput { .. }
+            | WindowEvent::Focused(_)
+            | WindowEvent::ScaleFactorChanged { .. }
+                if self.config.redraw == RedrawMode::OnDemand =>
+            {
+                window.request_redraw()
+            }
+            _ => (),
+        }
+
+        if paused != self.paused() {
+            tracing::debug!("rendering paused: {}", self.paused());
+
+            // The time spent paused is not a frame time.
+            self.stats.last_frame = None;
+            if !self.paused() {
+                window.request_redraw();
+            }
+        }
+    }
+
+    /// Requests the next frame if it is due and sets how long the event loop
+    /// waits for events. Called once all events have been handled.
+    pub fn update(&mut self, window: &Window, control_flow: &mut ControlFlow) {
+        if self.paused() || self.config.redraw == RedrawMode::OnDemand {
+            *control_flow = ControlFlow::Wait;
+            return;
+        }
+
+        match self.config.frame_interval {
+            Some(_) if Instant::now() < self.next_frame => {
+                *control_flow = ControlFlow::WaitUntil(self.next_frame);
+            }
+            _ => {
+                window.request_redraw();
+                *control_flow = ControlFlow::Poll;
+            }
+        }
+    }
+
+    /// Returns whether to render a frame for a redraw request, and records
+    /// its start if so.
+    pub fn begin_frame(&mut self) -> bool {
+        if self.paused() {
+            return false;
+        }
+
+        let now = Instant::now();
+        if let Some(interval) = self.config.frame_interval {
+            // Frames are scheduled from the previous deadline so waking up
+            // late does not lower the frame rate, unless a whole frame was
+            // missed.
+            self.next_frame = if now.saturating_duration_since(self.next_frame) > interval {
+                now + interval
+            } else {
+                self.next_frame + interval
+            };
+        }
+
+        self.stats.frame(now);
+        true
+    }
+}
+
+/// Returns the control flow that wakes the event loop for whichever of `a`
+/// and `b` needs it first.
+pub fn earliest(a: ControlFlow, b: ControlFlow) -> ControlFlow {
+    match (a, b) {
+        (ControlFlow::Poll, _) | (_, ControlFlow::Poll) => ControlFlow::Poll,
+        (ControlFlow::WaitUntil(a), ControlFlow::WaitUntil(b)) => ControlFlow::WaitUntil(a.min(b)),
+        (ControlFlow::WaitUntil(a), _) | (_, ControlFlow::WaitUntil(a)) => {
+            ControlFlow::WaitUntil(a)
+        }
+        _ => ControlFlow::Wait,
+    }
+}
+
+/// Frame times since the statistics were last logged.
+#[derive(Default)]
+struct FrameStats {
+    /// Start of the previous frame, `None` after a pause.
+    last_frame: Option<Instant>,
+    /// Start of the current interval.
+    since: Option<Instant>,
+    frame_times: Vec<Duration>,
+}
+
+impl FrameStats {
+    fn frame(&mut self, now: Instant) {
+        if let Some(last_frame) = self.last_frame {
+            self.frame_times.push(now - last_frame);
+        }
+        self.last_frame = Some(now);
+
+        let since = *self.since.get_or_insert(now);
+        if now - since >= STATS_INTERVAL && !self.frame_times.is_empty() {
+            self.log();
+            self.frame_times.clear();
+            self.since = Some(now);
+        }
+    }
+
+    fn log(&mut self) {
+        let summary = self.summary();
+
+        tracing::info!(
+            "frame time {:.2} ms ({:.1} fps), 1% low {:.1} fps, jitter {:.2} ms",
+            summary.average * 1000.0,
+            1.0 / summary.average,
+            1.0 / summary.low,
+            summary.jitter * 1000.0
+        );
+    }
+
+    /// Summarizes the frame times, sorting them.
+    fn summary(&mut self) -> Summary {
+        let seconds = self
+            .frame_times
+            .iter()
+            .map(Duration::as_secs_f64)
+            .collect::<Vec<_>>();
+        let count = seconds.len() as f64;
+
+        let average = seconds.iter().sum::<f64>() / count;
+        let variance = seconds.iter().map(|s| (s - average).powi(2)).sum::<f64>() / count;
+
+        self.frame_times.sort_unstable_by(|a, b| b.cmp(a));
+        let slowest = &self.frame_times[..self.frame_times.len().div_ceil(100)];
+        let low = slowest.iter().sum::<Duration>().as_secs_f64() / slowest.len() as f64;
+
+        Summary {
+            average,
+            low,
+            jitter: variance.sqrt(),
+        }
+    }
+}
+
+/// Frame time statistics, in seconds.
+#[derive(Debug)]
+struct Summary {
+    average: f64,
+    /// Average of the slowest 1% of the frames.
+    low: f64,
+    /// Standard deviation.
+    jitter: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn frame_times() {
+        let start = Instant::now();
+        let mut stats = FrameStats::default();
+
+        // The first frame has no frame time.
+        stats.frame(start);
+        assert!(stats.frame_times.is_empty());
+        stats.frame(start + ms(10));
+        stats.frame(start + ms(30));
+        assert_eq!(stats.frame_times, [ms(10), ms(20)]);
+
+        // The time spent paused is skipped.
+        stats.last_frame = None;
+        stats.frame(start + ms(1000));
+        stats.frame(start + ms(1016));
+        assert_eq!(stats.frame_times, [ms(10), ms(20), ms(16)]);
+
+        // The frame times are cleared once logged.
+        stats.frame(start + STATS_INTERVAL);
+        assert!(stats.frame_times.is_empty());
+        assert_eq!(stats.since, Some(start + STATS_INTERVAL));
+    }
+
+    #[test]
+    fn summary() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        // 198 frames of 10 ms and 2 of 30 ms: the slowest 1% are the two
+        // 30 ms frames.
+        let mut stats = FrameStats {
+            frame_times: [
+                vec![ms(10); 99],
+                vec![ms(30)],
+                vec![ms(10); 99],
+                vec![ms(30)],
+            ]
+            .concat(),
+            ..Default::default()
+        };
+        let summary = stats.summary();
+        assert!(close(summary.average, 0.0102), "{:?}", summary);
+        assert!(close(summary.low, 0.030), "{:?}", summary);
+        let variance = (198.0 * 0.0002f64.powi(2) + 2.0 * 0.0198f64.powi(2)) / 200.0;
+        assert!(close(summary.jitter, variance.sqrt()), "{:?}", summary);
+
+        // With fewer than 100 frames, the slowest frame is the 1% low.
+        let mut stats = FrameStats {
+            frame_times: vec![ms(16), ms(20), ms(16)],
+            ..Default::default()
+        };
+        assert!(close(stats.summary().low, 0.020));
+    }
+}
@@ -0,0 +1,91 @@
+//! Present mode selection.
+//!
+//! The present mode is chosen from a preference list: the first mode supported
+//! by the surface is used, falling back to `FIFO`, which is always supported.
+//! The list is configured by the `present.modes` setting, see
+//! [`config`](crate::config).
+
+use ash::vk;
+
+/// Preference when vsync is turned on at runtime.
+pub const VSYNC: &[vk::PresentModeKHR] = &[vk::PresentModeKHR::FIFO];
+
+/// Preference when vsync is turned off at runtime.
+pub const NO_VSYNC: &[vk::PresentModeKHR] =
+    &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE];
+
+/// Parses a list of `fifo`, `fifo-relaxed`, `mailbox` and `immediate`,
+/// skipping unknown modes.
+pub fn parse(modes: &[String]) -> Vec<vk::PresentModeKHR> {
+    modes
+        .iter()
+        .filter_map(|m| match m.as_str() {
+            "fifo" => Some(vk::PresentModeKHR::FIFO),
+            "fifo-relaxed" => Some(vk::PresentModeKHR::FIFO_RELAXED),
+            "mailbox" => Some(vk::PresentModeKHR::MAILBOX),
+            "immediate" => Some(vk::PresentModeKHR::IMMEDIATE),
+            _ => {
+                tracing::warn!("unknown present mode {:?}", m);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Returns the first mode of `preference` in `supported`, or `FIFO`.
+pub fn select(
+    preference: &[vk::PresentModeKHR],
+    supported: &[vk::PresentModeKHR],
+) -> vk::PresentModeKHR {
+    preference
+        .iter()
+        .copied()
+        .find(|m| supported.contains(m))
+        .unwrap_or(vk::PresentModeKHR::FIFO)
+}
+
+/// Returns whether presentation with `mode` waits for vertical blanking.
+pub fn is_vsync(mode: vk::PresentModeKHR) -> bool {
+    mode == vk::PresentModeKHR::FIFO || mode == vk::PresentModeKHR::FIFO_RELAXED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vk::PresentModeKHR as Mode;
+
+    #[test]
+    fn select_preferred() {
+        let all = &[
+            Mode::FIFO,
+            Mode::FIFO_RELAXED,
+            Mode::MAILBOX,
+            Mode::IMMEDIATE,
+        ];
+
+        // Preference, supported modes, expected mode.
+        let cases: &[(&[Mode], &[Mode], Mode)] = &[
+            (&[Mode::MAILBOX, Mode::IMMEDIATE], all, Mode::MAILBOX),
+            (
+                &[Mode::MAILBOX, Mode::IMMEDIATE],
+                &[Mode::FIFO, Mode::IMMEDIATE],
+                Mode::IMMEDIATE,
+            ),
+            // Falls back to FIFO.
+            (&[Mode::MAILBOX], &[Mode::FIFO, Mode::IMMEDIATE], Mode::FIFO),
+            (&[], all, Mode::FIFO),
+            (VSYNC, all, Mode::FIFO),
+        ];
+
+        for (preference, supported, expected) in cases {
+            assert_eq!(select(preference, supported), *expected, "{:?}", preference);
+        }
+    }
+
+    #[test]
+    fn parse_skips_unknown_modes() {
+        let modes = ["immediate", "vsync", "fifo-relaxed"].map(String::from);
+        assert_eq!(parse(&modes), [Mode::IMMEDIATE, Mode::FIFO_RELAXED]);
+    }
+}
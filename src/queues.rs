@@ -0,0 +1,274 @@
+//! Queue family selection.
+//!
+//! [`QueueFamilies::get`] picks the queue families of a physical device:
+//!
+//! - graphics and present: a single family supporting both is preferred, so
+//!   swapchain images can be used with `EXCLUSIVE` sharing.
+//! - compute: a family with compute but not graphics (async compute), if any.
+//! - transfer: a family with transfer but neither graphics nor compute (DMA),
+//!   if any.
+//!
+//! Roles without a dedicated family share the graphics family. Within a
+//! family, each role gets its own queue as long as the family has enough, see
+//! [`QueueFamilies::allocate`]; present always shares the graphics queue if
+//! they are in the same family. The created [`Queues`] are exposed to the
+//! renderer in `AppData::queues`, each with its [`Timeline`](crate::timeline::Timeline).
+
+use std::collections::BTreeMap;
+
+use ash::vk;
+use ash::{Device, Entry, Instance};
+
+use crate::AppData;
+
+#[derive(Copy, Clone, Debug)]
+pub struct QueueFamilies {
+    pub graphics: u32,
+    pub present: u32,
+    /// Dedicated compute family.
+    pub compute: Option<u32>,
+    /// Dedicated transfer family.
+    pub transfer: Option<u32>,
+}
+
+/// A queue of the device and the capabilities of its family.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Queue {
+    pub handle: vk::Queue,
+    pub family: u32,
+    /// Zero if timestamps are not supported.
+    pub timestamp_valid_bits: u32,
+}
+
+/// The queues used by the renderer. Roles may share a queue.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Queues {
+    pub graphics: Queue,
+    pub present: Queue,
+    pub compute: Queue,
+    pub transfer: Queue,
+}
+
+/// Queue indices allocated to the roles, by family.
+pub struct QueueAllocation {
+    /// Number of queues to create in each family.
+    pub counts: BTreeMap<u32, u32>,
+    graphics: (u32, u32),
+    present: (u32, u32),
+    compute: (u32, u32),
+    transfer: (u32, u32),
+}
+
+impl QueueFamilies {
+    /// Returns `None` if the device has no graphics or, with a surface, no
+    /// present capable family.
+    pub unsafe fn get(
+        entry: &Entry,
+        instance: &Instance,
+        data: &AppData,
+        physical_device: vk::PhysicalDevice,
+    ) -> Option<Self> {
+        let properties = instance.get_physical_device_queue_family_properties(physical_device);
+
+        // Without a surface nothing is presented, any family will do.
+        let surface = ash::extensions::khr::Surface::new(entry, instance);
+        let present = |index: u32| {
+            data.headless
+                || surface
+                    .get_physical_device_surface_support(physical_device, index, *data.surface)
+                    .unwrap()
+        };
+
+        Self::select(&properties, present)
+    }
+
+    fn select(
+        properties: &[vk::QueueFamilyProperties],
+        present: impl Fn(u32) -> bool,
+    ) -> Option<Self> {
+        let find = |matches: &dyn Fn(u32, vk::QueueFlags) -> bool| {
+            properties
+                .iter()
+                .enumerate()
+                .find(|(i, p)| p.queue_count > 0 && matches(*i as u32, p.queue_flags))
+                .map(|(i, _)| i as u32)
+        };
+
+        let (graphics, present) =
+            match find(&|i, flags| flags.contains(vk::QueueFlags::GRAPHICS) && present(i)) {
+                Some(family) => (family, family),
+                None => (
+                    find(&|_, flags| flags.contains(vk::QueueFlags::GRAPHICS))?,
+                    find(&|i, _| present(i))?,
+                ),
+            };
+
+        let compute = find(&|_, flags| {
+            flags.contains(vk::QueueFlags::COMPUTE) && !flags.contains(vk::QueueFlags::GRAPHICS)
+        });
+        let transfer = find(&|_, flags| {
+            flags.contains(vk::QueueFlags::TRANSFER)
+                && !flags.intersects(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        });
+
+        Some(Self {
+            graphics,
+            present,
+            compute,
+            transfer,
+        })
+    }
+
+    /// Allocates a queue to each role, a separate one if its family has
+    /// enough queues, and otherwise the last queue of the family.
+    pub fn allocate(&self, properties: &[vk::QueueFamilyProperties]) -> QueueAllocation {
+        let mut counts = BTreeMap::new();
+        let mut next = |family: u32| {
+            let count = counts.entry(family).or_insert(0);
+            let index = (*count).min(properties[family as usize].queue_count - 1);
+            *count = index + 1;
+            (family, index)
+        };
+
+        let graphics = next(self.graphics);
+        let present = if self.present == self.graphics {
+            graphics
+        } else {
+            next(self.present)
+        };
+        let compute = next(self.compute.unwrap_or(self.graphics));
+        let transfer = next(self.transfer.unwrap_or(self.graphics));
+
+        QueueAllocation {
+            counts,
+            graphics,
+            present,
+            compute,
+            transfer,
+        }
+    }
+}
+
+impl QueueAllocation {
+    /// Gets the allocated queues from `device`.
+    pub unsafe fn queues(
+        &self,
+        device: &Device,
+        properties: &[vk::QueueFamilyProperties],
+    ) -> Queues {
+        let queue = |(family, index): (u32, u32)| Queue {
+            handle: device.get_device_queue(family, index),
+            family,
+            timestamp_valid_bits: properties[family as usize].timestamp_valid_bits,
+        };
+
+        Queues {
+            graphics: queue(self.graphics),
+            present: queue(self.present),
+            compute: queue(self.compute),
+            transfer: queue(self.transfer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAPHICS: vk::QueueFlags = vk::QueueFlags::GRAPHICS;
+    const COMPUTE: vk::QueueFlags = vk::QueueFlags::COMPUTE;
+    const TRANSFER: vk::QueueFlags = vk::QueueFlags::TRANSFER;
+
+    fn family(flags: vk::QueueFlags, queue_count: u32) -> vk::QueueFamilyProperties {
+        vk::QueueFamilyProperties {
+            queue_flags: flags,
+            queue_count,
+            ..Default::default()
+        }
+    }
+
+    /// Graphics, present, compute and transfer families.
+    type Selected = (u32, u32, Option<u32>, Option<u32>);
+
+    #[test]
+    fn select() {
+        let all = GRAPHICS | COMPUTE | TRANSFER;
+
+        // Families, present capable families, expected selection.
+        let cases: &[(&[vk::QueueFamilyProperties], &[u32], Option<Selected>)] = &[
+            // A single family does everything.
+            (&[family(all, 1)], &[0], Some((0, 0, None, None))),
+            // Dedicated compute and transfer families.
+            (
+                &[
+                    family(all, 16),
+                    family(COMPUTE | TRANSFER, 8),
+                    family(TRANSFER, 2),
+                ],
+                &[0],
+                Some((0, 0, Some(1), Some(2))),
+            ),
+            // A family with graphics and present is preferred.
+            (
+                &[family(all, 1), family(all, 1)],
+                &[1],
+                Some((1, 1, None, None)),
+            ),
+            // Otherwise present uses a separate family.
+            (
+                &[family(all, 1), family(TRANSFER, 1)],
+                &[1],
+                Some((0, 1, None, Some(1))),
+            ),
+            // Families without queues are skipped.
+            (
+                &[family(all, 0), family(all, 1), family(COMPUTE, 0)],
+                &[0, 1],
+                Some((1, 1, None, None)),
+            ),
+            // No graphics family.
+            (&[family(COMPUTE | TRANSFER, 1)], &[0], None),
+            // No present family.
+            (&[family(all, 1)], &[], None),
+        ];
+
+        for (i, (properties, present, expected)) in cases.iter().enumerate() {
+            let families = QueueFamilies::select(properties, |index| present.contains(&index));
+            let families = families.map(|f| (f.graphics, f.present, f.compute, f.transfer));
+            assert_eq!(families, *expected, "case {}", i);
+        }
+    }
+
+    #[test]
+    fn allocate() {
+        let properties = [
+            family(GRAPHICS | COMPUTE | TRANSFER, 2),
+            family(COMPUTE | TRANSFER, 1),
+        ];
+        let families = QueueFamilies {
+            graphics: 0,
+            present: 0,
+            compute: Some(1),
+            transfer: None,
+        };
+
+        let allocation = families.allocate(&properties);
+        assert_eq!(allocation.counts, BTreeMap::from([(0, 2), (1, 1)]));
+        assert_eq!(allocation.graphics, (0, 0));
+        assert_eq!(allocation.present, (0, 0));
+        assert_eq!(allocation.compute, (1, 0));
+        // The graphics family has a second queue for transfers.
+        assert_eq!(allocation.transfer, (0, 1));
+
+        // Roles share the last queue of a family without enough queues.
+        let properties = [family(GRAPHICS | COMPUTE | TRANSFER, 1)];
+        let families = QueueFamilies {
+            compute: None,
+            ..families
+        };
+        let allocation = families.allocate(&properties);
+        assert_eq!(allocation.counts, BTreeMap::from([(0, 1)]));
+        assert_eq!(allocation.compute, (0, 0));
+        assert_eq!(allocation.transfer, (0, 0));
+    }
+}
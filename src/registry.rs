@@ -0,0 +1,100 @@
+//! Table of the live objects created from a device.
+//!
+//! Every [`Owned`](crate::owned::Owned) object is registered on creation with
+//! its type and, in debug builds, the backtrace of its creation. Names set with
+//! [`debug::set_object_name`](crate::debug::set_object_name) are recorded as
+//! well, so leaks can be reported before the validation layers complain about
+//! them when the device is destroyed.
+
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use ash::vk;
+
+struct LiveObject {
+    /// Creation order, to list objects in a stable order.
+    id: u64,
+    name: Option<String>,
+    backtrace: Option<Backtrace>,
+}
+
+#[derive(Default)]
+pub struct ObjectRegistry {
+    objects: RefCell<HashMap<(vk::ObjectType, u64), LiveObject>>,
+    next_id: Cell<u64>,
+}
+
+impl ObjectRegistry {
+    pub fn register(&self, object_type: vk::ObjectType, handle: u64) {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        let object = LiveObject {
+            id,
+            name: None,
+            backtrace: cfg!(debug_assertions).then(Backtrace::force_capture),
+        };
+        self.objects
+            .borrow_mut()
+            .insert((object_type, handle), object);
+    }
+
+    pub fn unregister(&self, object_type: vk::ObjectType, handle: u64) {
+        self.objects.borrow_mut().remove(&(object_type, handle));
+    }
+
+    /// Records the debug name of an object. Unregistered objects are ignored.
+    pub fn set_name(&self, object_type: vk::ObjectType, handle: u64, name: &str) {
+        if let Some(object) = self.objects.borrow_mut().get_mut(&(object_type, handle)) {
+            object.name = Some(name.to_owned());
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.borrow().len()
+    }
+
+    /// Returns the table of live objects, one per line in creation order.
+    pub fn dump(&self) -> String {
+        self.lines(false).concat()
+    }
+
+    /// Logs every live object as a leak, with its creation backtrace if
+    /// available.
+    pub fn report_leaks(&self) {
+        let leaks = self.lines(true);
+        if leaks.is_empty() {
+            return;
+        }
+
+        for leak in &leaks {
+            tracing::error!("leaked {}", leak.trim_end());
+        }
+        tracing::error!("{} Vulkan objects leaked", leaks.len());
+    }
+
+    fn lines(&self, backtraces: bool) -> Vec<String> {
+        let objects = self.objects.borrow();
+        let mut objects = objects.iter().collect::<Vec<_>>();
+        objects.sort_by_key(|(_, object)| object.id);
+
+        objects
+            .into_iter()
+            .map(|((object_type, handle), object)| {
+                let mut line = format!(
+                    "{:?} {:#x} {:?}",
+                    object_type,
+                    handle,
+                    object.name.as_deref().unwrap_or("<unnamed>")
+                );
+                if let (true, Some(backtrace)) = (backtraces, &object.backtrace) {
+                    write!(line, ", created at:\n{}", backtrace).unwrap();
+                }
+                line.push('\n');
+                line
+            })
+            .collect()
+    }
+}
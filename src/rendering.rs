@@ -0,0 +1,54 @@
+//! Rendering with `VK_KHR_dynamic_rendering` (core in 1.3).
+//!
+//! Replaces render pass and framebuffer objects when the device supports it.
+//! Layout transitions are recorded by the render graph.
+
+use ash::vk;
+use ash::Device;
+
+use crate::AppData;
+
+/// Clears `view` and begins rendering to it. The image must be in
+/// `COLOR_ATTACHMENT_OPTIMAL` layout.
+pub unsafe fn begin_rendering(
+    device: &Device,
+    data: &AppData,
+    command_buffer: vk::CommandBuffer,
+    view: vk::ImageView,
+    extent: vk::Extent2D,
+) {
+    let color_attachment = vk::RenderingAttachmentInfo::builder()
+        .image_view(view)
+        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(vk::AttachmentStoreOp::STORE)
+        .clear_value(vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            },
+        })
+        .build();
+
+    let render_area = vk::Rect2D::builder()
+        .offset(vk::Offset2D::default())
+        .extent(extent)
+        .build();
+
+    let color_attachments = &[color_attachment];
+    let info = vk::RenderingInfo::builder()
+        .render_area(render_area)
+        .layer_count(1)
+        .color_attachments(color_attachments);
+
+    match &data.dynamic_rendering_ext {
+        Some(ext) => ext.cmd_begin_rendering(command_buffer, &info),
+        None => device.cmd_begin_rendering(command_buffer, &info),
+    }
+}
+
+pub unsafe fn end_rendering(device: &Device, data: &AppData, command_buffer: vk::CommandBuffer) {
+    match &data.dynamic_rendering_ext {
+        Some(ext) => ext.cmd_end_rendering(command_buffer),
+        None => device.cmd_end_rendering(command_buffer),
+    }
+}
@@ -0,0 +1,35 @@
+//! Scenes drawn by the renderer.
+//!
+//! All scenes draw the triangle of `shader.vert`, the scene only decides which
+//! instances are drawn. Instance 0 is the full size triangle, instances 1 to
+//! 16 are laid out by the shader in a 4x4 grid of smaller triangles with
+//! rotated colors. The scene is configured by the `scene.name` setting, see
+//! [`config`](crate::config), and the golden image tests render each of them.
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Scene {
+    /// A single triangle.
+    #[default]
+    Triangle,
+    /// A 4x4 grid of triangles.
+    Grid,
+}
+
+impl Scene {
+    /// Parses `triangle` or `grid`.
+    pub fn parse(scene: &str) -> Option<Self> {
+        match scene {
+            "triangle" => Some(Self::Triangle),
+            "grid" => Some(Self::Grid),
+            _ => None,
+        }
+    }
+
+    /// Returns the instance count and first instance to draw.
+    pub fn instances(self) -> (u32, u32) {
+        match self {
+            Self::Triangle => (1, 0),
+            Self::Grid => (16, 1),
+        }
+    }
+}
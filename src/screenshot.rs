@@ -0,0 +1,324 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use ash::vk;
+use ash::{Device, Instance};
+
+use crate::debug;
+use crate::memory::create_buffer;
+use crate::owned::{Owned, SharedDevice};
+use crate::state::{Access, StateTracker};
+use crate::surface_format::{self, OutputTransfer};
+use crate::{begin_single_time_commands, AppData};
+
+/// Tightly packed 8-bit RGBA pixels.
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// A copy of a swapchain image recorded for a frame, see [`record_capture`].
+pub struct Capture {
+    pub command_buffer: vk::CommandBuffer,
+    readback: Readback,
+}
+
+/// Records a copy of swapchain image `index` into a new command buffer.
+///
+/// The command buffer must be submitted after the frame rendering to the
+/// image and before it is presented: once presented, the image belongs to the
+/// presentation engine until it is acquired again. The surface must allow
+/// `TRANSFER_SRC` usage.
+pub unsafe fn record_capture(
+    instance: &Instance,
+    device: &SharedDevice,
+    data: &AppData,
+    index: usize,
+) -> Capture {
+    let command_buffer = begin_single_time_commands(device, data);
+    debug::begin_label(data, command_buffer, "screenshot readback");
+
+    let image = data.swapchain_images[index];
+    let readback = Readback::new(
+        instance,
+        device,
+        data,
+        vk::SurfaceFormatKHR {
+            format: data.swapchain_format,
+            color_space: data.swapchain_color_space,
+        },
+        data.swapchain_extent,
+    );
+
+    // Written by any command of the frame, last transitioned for presentation.
+    let rendered = Access {
+        stage: vk::PipelineStageFlags2::ALL_COMMANDS,
+        access: vk::AccessFlags2::MEMORY_WRITE,
+        layout: vk::ImageLayout::PRESENT_SRC_KHR,
+    };
+
+    let mut tracker = StateTracker::new();
+    tracker.import_image(image, rendered);
+    tracker.import_buffer(readback.buffer(), Access::UNDEFINED);
+
+    tracker.use_image(image, Access::TRANSFER_READ);
+    tracker.use_buffer(readback.buffer(), Access::TRANSFER_WRITE);
+    tracker.flush(device, data, command_buffer);
+
+    record_copy(
+        device,
+        command_buffer,
+        image,
+        readback.buffer(),
+        data.swapchain_extent,
+    );
+
+    tracker.use_buffer(readback.buffer(), Access::HOST_READ);
+    tracker.use_image(image, Access::PRESENT);
+    tracker.flush(device, data, command_buffer);
+
+    debug::end_label(data, command_buffer);
+    device.end_command_buffer(command_buffer).unwrap();
+
+    Capture {
+        command_buffer,
+        readback,
+    }
+}
+
+impl Capture {
+    /// Reads the copied image once the command buffer completed.
+    pub unsafe fn finish(self, device: &SharedDevice, data: &AppData) -> Screenshot {
+        device.free_command_buffers(*data.command_pool, &[self.command_buffer]);
+        self.readback.read(device)
+    }
+}
+
+/// Records a copy of `image`, in `TRANSFER_SRC_OPTIMAL` layout, into the
+/// tightly packed `buffer`.
+pub unsafe fn record_copy(
+    device: &Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    buffer: vk::Buffer,
+    extent: vk::Extent2D,
+) {
+    let region = vk::BufferImageCopy::builder()
+        .buffer_offset(0)
+        .buffer_row_length(0)
+        .buffer_image_height(0)
+        .image_subresource(vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            mip_level: 0,
+            base_array_layer: 0,
+            layer_count: 1,
+        })
+        .image_offset(vk::Offset3D::default())
+        .image_extent(vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        })
+        .build();
+
+    device.cmd_copy_image_to_buffer(
+        command_buffer,
+        image,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        buffer,
+        &[region],
+    );
+}
+
+/// A host visible buffer an image is copied into, see [`record_copy`].
+pub struct Readback {
+    buffer: Owned<vk::Buffer>,
+    memory: Owned<vk::DeviceMemory>,
+    size: vk::DeviceSize,
+    format: vk::SurfaceFormatKHR,
+    extent: vk::Extent2D,
+}
+
+impl Readback {
+    /// Creates the buffer for an image of `extent`. `format` is the image
+    /// format with the surface color space the image was rendered for.
+    pub unsafe fn new(
+        instance: &Instance,
+        device: &SharedDevice,
+        data: &AppData,
+        format: vk::SurfaceFormatKHR,
+        extent: vk::Extent2D,
+    ) -> Self {
+        let size = extent.width as vk::DeviceSize
+            * extent.height as vk::DeviceSize
+            * bytes_per_pixel(format.format);
+
+        let (buffer, memory) = create_buffer(
+            instance,
+            device,
+            data,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &[],
+        );
+
+        debug::set_object_name(device, data, *buffer, "screenshot readback");
+        debug::set_object_name(device, data, *memory, "screenshot readback memory");
+
+        Self {
+            buffer,
+            memory,
+            size,
+            format,
+            extent,
+        }
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        *self.buffer
+    }
+
+    /// Reads the pixels once the copy completed.
+    pub unsafe fn read(self, device: &SharedDevice) -> Screenshot {
+        let ptr = device
+            .map_memory(*self.memory, 0, self.size, vk::MemoryMapFlags::empty())
+            .unwrap();
+        let pixels = std::slice::from_raw_parts(ptr as *const u8, self.size as usize).to_vec();
+        device.unmap_memory(*self.memory);
+
+        Screenshot {
+            width: self.extent.width,
+            height: self.extent.height,
+            pixels: convert_to_rgba(self.format, pixels),
+        }
+    }
+}
+
+fn bytes_per_pixel(format: vk::Format) -> vk::DeviceSize {
+    match format {
+        vk::Format::R16G16B16A16_SFLOAT => 8,
+        _ => 4,
+    }
+}
+
+/// Converts pixels in `format` to 8-bit sRGB RGBA.
+///
+/// 8-bit formats are stored with the sRGB transfer function already applied,
+/// which is what PNG expects, so only the channel order needs to be fixed.
+/// Other formats are decoded according to the output transfer function of
+/// the shader and encoded again, HDR values are clipped to SDR white.
+fn convert_to_rgba(format: vk::SurfaceFormatKHR, mut pixels: Vec<u8>) -> Vec<u8> {
+    let transfer = surface_format::output_transfer(format);
+
+    match format.format {
+        vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+            pixels
+        }
+        vk::Format::R8G8B8A8_SRGB | vk::Format::R8G8B8A8_UNORM => pixels,
+        vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 => pixels
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let pixel = u32::from_ne_bytes(pixel.try_into().unwrap());
+                let channel = |shift: u32| ((pixel >> shift) & 0x3ff) as f32 / 1023.0;
+
+                let (r, b) = if format.format == vk::Format::A2B10G10R10_UNORM_PACK32 {
+                    (channel(0), channel(20))
+                } else {
+                    (channel(20), channel(0))
+                };
+                let alpha = (pixel >> 30) as f32 / 3.0;
+
+                encode_srgb(transfer, [r, channel(10), b], alpha)
+            })
+            .collect(),
+        vk::Format::R16G16B16A16_SFLOAT => pixels
+            .chunks_exact(8)
+            .flat_map(|pixel| {
+                let channel = |i: usize| f16_to_f32(u16::from_ne_bytes([pixel[i], pixel[i + 1]]));
+                encode_srgb(transfer, [channel(0), channel(2), channel(4)], channel(6))
+            })
+            .collect(),
+        _ => {
+            tracing::warn!("unsupported screenshot format {:?}", format.format);
+            pixels
+        }
+    }
+}
+
+/// Encodes a color written by the shader with `transfer` as 8-bit sRGB.
+fn encode_srgb(transfer: OutputTransfer, color: [f32; 3], alpha: f32) -> [u8; 4] {
+    let linear = match transfer {
+        // Already encoded.
+        OutputTransfer::Srgb => color,
+        OutputTransfer::None => color.map(srgb_oetf),
+        OutputTransfer::Pq => {
+            let scale = 10000.0 / surface_format::PAPER_WHITE;
+            let [r, g, b] = color.map(|c| pq_eotf(c) * scale);
+
+            // Rec. 2020 to Rec. 709.
+            [
+                1.6605 * r - 0.5876 * g - 0.0728 * b,
+                -0.1246 * r + 1.1329 * g - 0.0083 * b,
+                -0.0182 * r - 0.1006 * g + 1.1187 * b,
+            ]
+            .map(srgb_oetf)
+        }
+    };
+
+    let quantize = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let [r, g, b] = linear.map(quantize);
+    [r, g, b, quantize(alpha)]
+}
+
+fn srgb_oetf(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// ST 2084 EOTF, returns units of 10000 nits.
+fn pq_eotf(c: f32) -> f32 {
+    const M1: f32 = 0.159_301_76;
+    const M2: f32 = 78.843_75;
+    const C1: f32 = 0.835_937_5;
+    const C2: f32 = 18.851_563;
+    const C3: f32 = 18.6875;
+
+    let p = c.clamp(0.0, 1.0).powf(1.0 / M2);
+    ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1)
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f32;
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+pub fn write_png(path: &Path, screenshot: &Screenshot) -> Result<(), png::EncodingError> {
+    let file = File::create(path)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), screenshot.width, screenshot.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&screenshot.pixels)?;
+
+    Ok(())
+}
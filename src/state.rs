@@ -0,0 +1,444 @@
+//! Resource state tracking.
+//!
+//! A [`StateTracker`] remembers the layout of every image and the last
+//! accesses of every image and buffer recorded into a command buffer. Using a
+//! resource declares how it is accessed next, and [`StateTracker::flush`]
+//! records the barriers needed to get there, skipping them if the resource is
+//! already in the right state (e.g. two reads in the same layout).
+
+use std::collections::HashMap;
+
+use ash::vk;
+use ash::Device;
+
+use crate::barrier::{self, BufferBarrier, ImageBarrier};
+use crate::AppData;
+
+/// How a resource is accessed by a command. `layout` is ignored for buffers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub stage: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+    pub layout: vk::ImageLayout,
+}
+
+impl Access {
+    pub const COLOR_ATTACHMENT: Self = Self {
+        stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        access: vk::AccessFlags2::from_raw(
+            vk::AccessFlags2::COLOR_ATTACHMENT_READ.as_raw()
+                | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw(),
+        ),
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    };
+
+    #[allow(dead_code)]
+    pub const DEPTH_ATTACHMENT: Self = Self {
+        stage: vk::PipelineStageFlags2::from_raw(
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS.as_raw()
+                | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS.as_raw(),
+        ),
+        access: vk::AccessFlags2::from_raw(
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ.as_raw()
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw(),
+        ),
+        layout: vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+    };
+
+    #[allow(dead_code)]
+    pub const FRAGMENT_SAMPLED: Self = Self {
+        stage: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        access: vk::AccessFlags2::SHADER_SAMPLED_READ,
+        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    };
+
+    pub const TRANSFER_READ: Self = Self {
+        stage: vk::PipelineStageFlags2::COPY,
+        access: vk::AccessFlags2::TRANSFER_READ,
+        layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    };
+
+    pub const TRANSFER_WRITE: Self = Self {
+        stage: vk::PipelineStageFlags2::COPY,
+        access: vk::AccessFlags2::TRANSFER_WRITE,
+        layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    };
+
+    pub const HOST_READ: Self = Self {
+        stage: vk::PipelineStageFlags2::HOST,
+        access: vk::AccessFlags2::HOST_READ,
+        layout: vk::ImageLayout::GENERAL,
+    };
+
+    /// Presentation is synchronized by a semaphore, only the layout matters.
+    pub const PRESENT: Self = Self {
+        stage: vk::PipelineStageFlags2::NONE,
+        access: vk::AccessFlags2::NONE,
+        layout: vk::ImageLayout::PRESENT_SRC_KHR,
+    };
+
+    /// A swapchain image after it was acquired. The frame waits for the
+    /// acquire semaphore at the color attachment output stage, barriers must
+    /// start from that stage to chain with the wait.
+    pub const ACQUIRED: Self = Self {
+        stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        access: vk::AccessFlags2::NONE,
+        layout: vk::ImageLayout::UNDEFINED,
+    };
+
+    /// The state of a resource that was not used yet.
+    pub const UNDEFINED: Self = Self {
+        stage: vk::PipelineStageFlags2::NONE,
+        access: vk::AccessFlags2::NONE,
+        layout: vk::ImageLayout::UNDEFINED,
+    };
+
+    pub fn is_write(&self) -> bool {
+        self.access.intersects(WRITE_ACCESS)
+    }
+}
+
+const WRITE_ACCESS: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::SHADER_WRITE.as_raw()
+        | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags2::HOST_WRITE.as_raw()
+        | vk::AccessFlags2::MEMORY_WRITE.as_raw(),
+);
+
+/// The accesses since the last write of a resource.
+#[derive(Copy, Clone, Debug, Default)]
+struct State {
+    /// Stages and accesses of the last write (or layout transition).
+    write_stage: vk::PipelineStageFlags2,
+    write_access: vk::AccessFlags2,
+    /// Stages that read the resource since the last write, and the accesses
+    /// the write was made visible to.
+    read_stage: vk::PipelineStageFlags2,
+    read_access: vk::AccessFlags2,
+}
+
+impl State {
+    /// Updates the state for `next` and returns the source stage and access of
+    /// the required barrier, or `None` if no barrier is required.
+    fn transition(
+        &mut self,
+        next: Access,
+        layout_change: bool,
+    ) -> Option<(vk::PipelineStageFlags2, vk::AccessFlags2)> {
+        if layout_change || next.is_write() {
+            // Writes must wait for all previous accesses. Layout transitions
+            // are writes: later reads in the same stage are made visible here,
+            // reads in other stages wait for the transition.
+            let src = (self.write_stage | self.read_stage, self.write_access);
+
+            *self = if next.is_write() {
+                Self {
+                    write_stage: next.stage,
+                    write_access: next.access & WRITE_ACCESS,
+                    ..Default::default()
+                }
+            } else {
+                Self {
+                    write_stage: next.stage,
+                    write_access: next.access,
+                    read_stage: next.stage,
+                    read_access: next.access,
+                }
+            };
+
+            // The first write of a resource doesn't need to wait for anything.
+            if !layout_change && src.0.is_empty() {
+                return None;
+            }
+
+            return Some(src);
+        }
+
+        // Reads only wait for the last write, once per stage and access.
+        if self.write_stage.is_empty()
+            || (self.read_stage.contains(next.stage) && self.read_access.contains(next.access))
+        {
+            self.read_stage |= next.stage;
+            return None;
+        }
+
+        let src = (self.write_stage, self.write_access);
+        self.read_stage |= next.stage;
+        self.read_access |= next.access;
+
+        Some(src)
+    }
+}
+
+struct ImageEntry {
+    range: vk::ImageSubresourceRange,
+    layout: vk::ImageLayout,
+    state: State,
+}
+
+/// Tracks the state of resources within a command buffer.
+///
+/// Resources must be imported with their state at the start of the command
+/// buffer. The tracker does not know about implicit transitions (e.g. by a
+/// render pass), these must be reported with [`StateTracker::assume_image`].
+#[derive(Default)]
+pub struct StateTracker {
+    images: HashMap<vk::Image, ImageEntry>,
+    buffers: HashMap<vk::Buffer, State>,
+    pending_images: Vec<ImageBarrier>,
+    pending_buffers: Vec<BufferBarrier>,
+}
+
+impl StateTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking the first mip level and layer of a color image that was
+    /// last accessed by `previous`.
+    pub fn import_image(&mut self, image: vk::Image, previous: Access) {
+        let range = ImageBarrier::color(image).range;
+        self.import_image_range(image, range, previous);
+    }
+
+    pub fn import_image_range(
+        &mut self,
+        image: vk::Image,
+        range: vk::ImageSubresourceRange,
+        previous: Access,
+    ) {
+        let state = if previous.is_write() {
+            State {
+                write_stage: previous.stage,
+                write_access: previous.access & WRITE_ACCESS,
+                ..Default::default()
+            }
+        } else {
+            State {
+                read_stage: previous.stage,
+                ..Default::default()
+            }
+        };
+
+        self.images.insert(
+            image,
+            ImageEntry {
+                range,
+                layout: previous.layout,
+                state,
+            },
+        );
+    }
+
+    /// Starts tracking a buffer that was last accessed by `previous`.
+    pub fn import_buffer(&mut self, buffer: vk::Buffer, previous: Access) {
+        let mut state = State::default();
+        state.transition(previous, false);
+        self.buffers.insert(buffer, state);
+    }
+
+    /// Declares that `image` is accessed by `next`, keeping its contents.
+    pub fn use_image(&mut self, image: vk::Image, next: Access) {
+        self.transition_image(image, next, false);
+    }
+
+    /// Declares that `image` is accessed by `next` and its previous contents
+    /// are not needed, e.g. because it is cleared.
+    pub fn discard_image(&mut self, image: vk::Image, next: Access) {
+        self.transition_image(image, next, true);
+    }
+
+    fn transition_image(&mut self, image: vk::Image, next: Access, discard: bool) {
+        let entry = self
+            .images
+            .get_mut(&image)
+            .unwrap_or_else(|| panic!("image {:?} is not tracked", image));
+
+        let layout_change = discard || entry.layout != next.layout;
+        if let Some((src_stage, src_access)) = entry.state.transition(next, layout_change) {
+            let old_layout = if discard {
+                vk::ImageLayout::UNDEFINED
+            } else {
+                entry.layout
+            };
+
+            self.pending_images.push(ImageBarrier {
+                range: entry.range,
+                ..ImageBarrier::color(image)
+                    .layout(old_layout, next.layout)
+                    .src(src_stage, src_access)
+                    .dst(next.stage, next.access)
+            });
+        }
+
+        entry.layout = next.layout;
+    }
+
+    /// Declares that `buffer` is accessed by `next`.
+    pub fn use_buffer(&mut self, buffer: vk::Buffer, next: Access) {
+        let state = self
+            .buffers
+            .get_mut(&buffer)
+            .unwrap_or_else(|| panic!("buffer {:?} is not tracked", buffer));
+
+        if let Some((src_stage, src_access)) = state.transition(next, false) {
+            self.pending_buffers.push(
+                BufferBarrier::whole(buffer)
+                    .src(src_stage, src_access)
+                    .dst(next.stage, next.access),
+            );
+        }
+    }
+
+    /// Records that `image` was transitioned to `state` without a barrier from
+    /// the tracker, e.g. by the final layout of a render pass.
+    #[allow(dead_code)]
+    pub fn assume_image(&mut self, image: vk::Image, state: Access) {
+        let range = self.images[&image].range;
+        self.import_image_range(image, range, state);
+    }
+
+    /// Returns the current layout of `image`.
+    #[allow(dead_code)]
+    pub fn image_layout(&self, image: vk::Image) -> vk::ImageLayout {
+        self.images[&image].layout
+    }
+
+    /// Returns the barriers that will be recorded by the next flush.
+    pub fn pending(&self) -> (&[BufferBarrier], &[ImageBarrier]) {
+        (&self.pending_buffers, &self.pending_images)
+    }
+
+    /// Records the barriers for all resource uses since the last flush as a
+    /// single pipeline barrier.
+    pub unsafe fn flush(
+        &mut self,
+        device: &Device,
+        data: &AppData,
+        command_buffer: vk::CommandBuffer,
+    ) {
+        barrier::pipeline_barrier(
+            device,
+            data,
+            command_buffer,
+            &self.pending_buffers,
+            &self.pending_images,
+        );
+
+        self.pending_buffers.clear();
+        self.pending_images.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    const SHADER_READ: Access = Access {
+        stage: vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        access: vk::AccessFlags2::SHADER_SAMPLED_READ,
+        layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    };
+
+    fn written(access: Access) -> State {
+        let mut state = State::default();
+        state.transition(access, false);
+        state
+    }
+
+    #[test]
+    fn first_write_needs_no_barrier() {
+        let mut state = State::default();
+        assert_eq!(state.transition(Access::TRANSFER_WRITE, false), None);
+    }
+
+    #[test]
+    fn first_layout_change_waits_for_previous_stage() {
+        let mut tracker = StateTracker::new();
+        let image = vk::Image::from_raw(1);
+        tracker.import_image(image, Access::ACQUIRED);
+        tracker.discard_image(image, Access::COLOR_ATTACHMENT);
+
+        let (_, images) = tracker.pending();
+        assert_eq!(images.len(), 1);
+        assert_eq!(
+            images[0].src_stage,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(images[0].old_layout, vk::ImageLayout::UNDEFINED);
+    }
+
+    #[test]
+    fn read_after_write_waits_for_write() {
+        let mut state = written(Access::TRANSFER_WRITE);
+        assert_eq!(
+            state.transition(SHADER_READ, false),
+            Some((
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_WRITE
+            ))
+        );
+    }
+
+    #[test]
+    fn repeated_read_needs_no_barrier() {
+        let mut state = written(Access::TRANSFER_WRITE);
+        state.transition(SHADER_READ, false);
+        assert_eq!(state.transition(SHADER_READ, false), None);
+    }
+
+    #[test]
+    fn write_after_read_waits_for_reads() {
+        let mut state = written(Access::TRANSFER_WRITE);
+        state.transition(SHADER_READ, false);
+
+        let (stage, access) = state.transition(Access::COLOR_ATTACHMENT, false).unwrap();
+        assert_eq!(
+            stage,
+            vk::PipelineStageFlags2::COPY | vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(access, vk::AccessFlags2::TRANSFER_WRITE);
+    }
+
+    #[test]
+    fn write_after_write_waits_for_write() {
+        let mut state = written(Access::TRANSFER_WRITE);
+        assert_eq!(
+            state.transition(Access::TRANSFER_WRITE, false),
+            Some((
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_WRITE
+            ))
+        );
+    }
+
+    #[test]
+    fn layout_change_of_read_is_a_write() {
+        let mut state = written(Access::TRANSFER_WRITE);
+        state.transition(Access::TRANSFER_READ, false);
+
+        assert!(state.transition(SHADER_READ, true).is_some());
+        // The transition made the image visible to the shader reads.
+        assert_eq!(state.transition(SHADER_READ, false), None);
+    }
+
+    #[test]
+    fn read_in_other_stage_waits_for_layout_change() {
+        let mut state = written(Access::TRANSFER_WRITE);
+        state.transition(Access::TRANSFER_READ, true);
+        assert_eq!(state.transition(Access::TRANSFER_READ, false), None);
+
+        assert_eq!(
+            state.transition(SHADER_READ, false),
+            Some((
+                vk::PipelineStageFlags2::COPY,
+                vk::AccessFlags2::TRANSFER_READ
+            ))
+        );
+        assert_eq!(state.transition(SHADER_READ, false), None);
+    }
+}
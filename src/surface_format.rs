@@ -0,0 +1,232 @@
+//! Surface format and color space selection.
+//!
+//! The swapchain format is chosen by a [`FormatPolicy`] configured by the
+//! `surface.format` setting (see [`config`](crate::config)): `srgb` (default),
+//! `unorm`, `10bit`, `scrgb` or `hdr10`. Each policy has a list of
+//! candidate formats; if the surface supports none of them, the `srgb`
+//! candidates are tried, then the first supported format is used.
+//!
+//! Color spaces other than sRGB need `VK_EXT_swapchain_colorspace`. The
+//! fragment shader is told by [`OutputTransfer`] which transfer function the
+//! presentation engine expects it to apply.
+
+use ash::vk;
+
+/// How to pick the swapchain format.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FormatPolicy {
+    /// 8-bit sRGB format, encoded by the hardware.
+    #[default]
+    Srgb,
+    /// 8-bit UNORM format with sRGB color space, encoded by the shader.
+    Unorm,
+    /// 10-bit UNORM format with sRGB color space, encoded by the shader.
+    TenBit,
+    /// 16-bit float linear extended sRGB (scRGB).
+    ScRgb,
+    /// 10-bit HDR10 (Rec. 2020 primaries, ST 2084 transfer).
+    Hdr10,
+}
+
+/// Transfer function applied by the fragment shader to its linear output.
+///
+/// Passed to the shader as specialization constant 0.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(u32)]
+pub enum OutputTransfer {
+    /// Written as is: the format encodes (`*_SRGB`) or the color space is
+    /// linear.
+    #[default]
+    None = 0,
+    /// sRGB transfer function.
+    Srgb = 1,
+    /// Conversion to Rec. 2020 and ST 2084 (PQ) transfer function.
+    Pq = 2,
+}
+
+/// Luminance of SDR white in HDR10 output, in nits (ITU-R BT.2408). Must match
+/// `PAPER_WHITE` in `shader.frag`.
+pub const PAPER_WHITE: f32 = 203.0;
+
+const fn format(format: vk::Format, color_space: vk::ColorSpaceKHR) -> vk::SurfaceFormatKHR {
+    vk::SurfaceFormatKHR {
+        format,
+        color_space,
+    }
+}
+
+const SRGB: &[vk::SurfaceFormatKHR] = &[
+    format(vk::Format::B8G8R8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+    format(vk::Format::R8G8B8A8_SRGB, vk::ColorSpaceKHR::SRGB_NONLINEAR),
+];
+
+const UNORM: &[vk::SurfaceFormatKHR] = &[
+    format(
+        vk::Format::B8G8R8A8_UNORM,
+        vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    ),
+    format(
+        vk::Format::R8G8B8A8_UNORM,
+        vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    ),
+];
+
+const TEN_BIT: &[vk::SurfaceFormatKHR] = &[
+    format(
+        vk::Format::A2B10G10R10_UNORM_PACK32,
+        vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    ),
+    format(
+        vk::Format::A2R10G10B10_UNORM_PACK32,
+        vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    ),
+];
+
+const SCRGB: &[vk::SurfaceFormatKHR] = &[format(
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT,
+)];
+
+const HDR10: &[vk::SurfaceFormatKHR] = &[
+    format(
+        vk::Format::A2B10G10R10_UNORM_PACK32,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    ),
+    format(
+        vk::Format::A2R10G10B10_UNORM_PACK32,
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT,
+    ),
+];
+
+impl FormatPolicy {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy.trim() {
+            "srgb" => Some(Self::Srgb),
+            "unorm" => Some(Self::Unorm),
+            "10bit" => Some(Self::TenBit),
+            "scrgb" => Some(Self::ScRgb),
+            "hdr10" => Some(Self::Hdr10),
+            _ => None,
+        }
+    }
+
+    /// Formats in order of preference.
+    fn candidates(self) -> &'static [vk::SurfaceFormatKHR] {
+        match self {
+            Self::Srgb => SRGB,
+            Self::Unorm => UNORM,
+            Self::TenBit => TEN_BIT,
+            Self::ScRgb => SCRGB,
+            Self::Hdr10 => HDR10,
+        }
+    }
+}
+
+/// Returns the first candidate of `policy` in `supported`, falling back to
+/// the sRGB candidates and then to the first supported format.
+pub fn select(policy: FormatPolicy, supported: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
+    let find = |candidates: &[vk::SurfaceFormatKHR]| {
+        candidates.iter().copied().find(|c| {
+            supported
+                .iter()
+                .any(|s| s.format == c.format && s.color_space == c.color_space)
+        })
+    };
+
+    if let Some(format) = find(policy.candidates()) {
+        return format;
+    }
+
+    tracing::warn!("no surface format supported for policy {:?}", policy);
+    find(SRGB).unwrap_or(supported[0])
+}
+
+/// Returns the transfer function the shader applies for `format`.
+pub fn output_transfer(format: vk::SurfaceFormatKHR) -> OutputTransfer {
+    match format.color_space {
+        vk::ColorSpaceKHR::SRGB_NONLINEAR => match format.format {
+            vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32 => OutputTransfer::None,
+            // Float formats cannot encode and are presented as linear.
+            vk::Format::R16G16B16A16_SFLOAT => OutputTransfer::None,
+            _ => OutputTransfer::Srgb,
+        },
+        vk::ColorSpaceKHR::HDR10_ST2084_EXT => OutputTransfer::Pq,
+        _ => OutputTransfer::None,
+    }
+}
+
+/// Mastering display metadata for HDR10 output: Rec. 2020 primaries, D65
+/// white point and a 1000 nits display.
+pub fn hdr10_metadata() -> vk::HdrMetadataEXT {
+    let xy = |x, y| vk::XYColorEXT { x, y };
+
+    vk::HdrMetadataEXT::builder()
+        .display_primary_red(xy(0.708, 0.292))
+        .display_primary_green(xy(0.170, 0.797))
+        .display_primary_blue(xy(0.131, 0.046))
+        .white_point(xy(0.3127, 0.3290))
+        .max_luminance(1000.0)
+        .min_luminance(0.001)
+        .max_content_light_level(1000.0)
+        .max_frame_average_light_level(400.0)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use vk::ColorSpaceKHR as ColorSpace;
+    use vk::Format;
+
+    #[test]
+    fn select_preferred() {
+        let bgra_srgb = format(Format::B8G8R8A8_SRGB, ColorSpace::SRGB_NONLINEAR);
+        let rgba_srgb = format(Format::R8G8B8A8_SRGB, ColorSpace::SRGB_NONLINEAR);
+        let bgra_unorm = format(Format::B8G8R8A8_UNORM, ColorSpace::SRGB_NONLINEAR);
+        let rgb10 = format(Format::A2B10G10R10_UNORM_PACK32, ColorSpace::SRGB_NONLINEAR);
+        let hdr10 = format(
+            Format::A2B10G10R10_UNORM_PACK32,
+            ColorSpace::HDR10_ST2084_EXT,
+        );
+        let scrgb = format(
+            Format::R16G16B16A16_SFLOAT,
+            ColorSpace::EXTENDED_SRGB_LINEAR_EXT,
+        );
+        let other = format(Format::R5G6B5_UNORM_PACK16, ColorSpace::SRGB_NONLINEAR);
+
+        // Policy, supported formats, expected format.
+        let cases = [
+            (
+                FormatPolicy::Srgb,
+                vec![bgra_unorm, rgba_srgb, bgra_srgb],
+                bgra_srgb,
+            ),
+            (FormatPolicy::Srgb, vec![bgra_unorm, rgba_srgb], rgba_srgb),
+            (FormatPolicy::Unorm, vec![bgra_srgb, bgra_unorm], bgra_unorm),
+            (FormatPolicy::TenBit, vec![bgra_srgb, rgb10], rgb10),
+            (FormatPolicy::ScRgb, vec![bgra_srgb, scrgb], scrgb),
+            (FormatPolicy::Hdr10, vec![bgra_srgb, rgb10, hdr10], hdr10),
+            // The color space has to match as well.
+            (FormatPolicy::Hdr10, vec![rgb10, bgra_srgb], bgra_srgb),
+            // Falls back to sRGB, then to the first supported format.
+            (FormatPolicy::ScRgb, vec![bgra_unorm, bgra_srgb], bgra_srgb),
+            (FormatPolicy::Srgb, vec![other, bgra_unorm], other),
+        ];
+
+        for (policy, supported, expected) in cases {
+            assert_eq!(select(policy, &supported), expected, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn output_transfer_of_candidates() {
+        assert_eq!(output_transfer(SRGB[0]), OutputTransfer::None);
+        assert_eq!(output_transfer(UNORM[0]), OutputTransfer::Srgb);
+        assert_eq!(output_transfer(TEN_BIT[0]), OutputTransfer::Srgb);
+        assert_eq!(output_transfer(SCRGB[0]), OutputTransfer::None);
+        assert_eq!(output_transfer(HDR10[0]), OutputTransfer::Pq);
+    }
+}
@@ -0,0 +1,205 @@
+//! GPU timelines of queue submissions.
+//!
+//! Every queue has a [`Timeline`], shared by the roles using the queue, see
+//! [`queues`](crate::queues). Every submit through it signals the next value
+//! of the timeline, so the host can wait for specific submits instead of the
+//! whole queue and submits to other queues can depend on them, e.g. on an
+//! upload, see [`Timeline::dependency`]. Timeline semaphores are used if
+//! supported (Vulkan 1.2 or `VK_KHR_timeline_semaphore`), otherwise every
+//! submit signals a fence and dependencies between queues are resolved on the
+//! host.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+
+use ash::prelude::VkResult;
+use ash::vk;
+use ash::Device;
+
+use crate::barrier::{self, SemaphoreSubmit};
+use crate::owned::{Owned, SharedDevice};
+use crate::{debug, AppData};
+
+#[derive(Default)]
+pub struct Timeline {
+    queue: vk::Queue,
+    /// Null without timeline semaphores.
+    semaphore: Owned<vk::Semaphore>,
+    /// Value signaled by the last submit.
+    submitted: Cell<u64>,
+    /// Value known to have been reached.
+    completed: Cell<u64>,
+    /// Without timeline semaphores, the fences of the submits that are not
+    /// known to have completed, by value.
+    fences: RefCell<VecDeque<(u64, Owned<vk::Fence>)>>,
+    /// Signaled fences, reset and ready to be reused.
+    free_fences: RefCell<Vec<Owned<vk::Fence>>>,
+}
+
+impl Timeline {
+    pub unsafe fn new(device: &SharedDevice, data: &AppData, queue: vk::Queue, name: &str) -> Self {
+        let semaphore = if data.timeline_semaphore {
+            let mut type_info = vk::SemaphoreTypeCreateInfo::builder()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0);
+            let info = vk::SemaphoreCreateInfo::builder().push_next(&mut type_info);
+
+            let semaphore = Owned::new(device, device.create_semaphore(&info, None).unwrap());
+            debug::set_object_name(device, data, *semaphore, name);
+            semaphore
+        } else {
+            Owned::default()
+        };
+
+        Self {
+            queue,
+            semaphore,
+            ..Default::default()
+        }
+    }
+
+    /// Submits `command_buffers` to the queue and returns the timeline value
+    /// signaled once they complete.
+    pub unsafe fn submit(
+        &self,
+        device: &SharedDevice,
+        data: &AppData,
+        wait: &[SemaphoreSubmit],
+        command_buffers: &[vk::CommandBuffer],
+        signal: &[SemaphoreSubmit],
+    ) -> VkResult<u64> {
+        let value = self.submitted.get() + 1;
+
+        let mut signal = signal.to_vec();
+        let mut fence = None;
+        if *self.semaphore != vk::Semaphore::null() {
+            signal.push(SemaphoreSubmit {
+                semaphore: *self.semaphore,
+                stage: vk::PipelineStageFlags2::ALL_COMMANDS,
+                value,
+            });
+        } else {
+            fence = Some(self.free_fences.borrow_mut().pop().unwrap_or_else(|| {
+                let info = vk::FenceCreateInfo::builder();
+                Owned::new(device, device.create_fence(&info, None).unwrap())
+            }));
+        }
+
+        let submit = barrier::Submit {
+            wait,
+            command_buffers,
+            signal: &signal,
+        };
+        // After a failed submit the state of the fence is unknown, it is
+        // dropped instead of being tracked.
+        let handle = fence.as_ref().map(|f| **f).unwrap_or_default();
+        barrier::queue_submit(device, data, self.queue, &[submit], handle)?;
+
+        if let Some(fence) = fence {
+            self.fences.borrow_mut().push_back((value, fence));
+        }
+        self.submitted.set(value);
+        Ok(value)
+    }
+
+    /// Returns the value signaled by the last submit.
+    pub fn submitted(&self) -> u64 {
+        self.submitted.get()
+    }
+
+    /// Returns the value reached by the queue as of the last query, without
+    /// querying the device.
+    pub fn last_completed(&self) -> u64 {
+        self.completed.get()
+    }
+
+    /// Returns the value reached by the queue. Does not block.
+    pub unsafe fn completed(&self, device: &Device, data: &AppData) -> VkResult<u64> {
+        if *self.semaphore != vk::Semaphore::null() {
+            let value = match &data.timeline_semaphore_ext {
+                Some(ext) => ext.get_semaphore_counter_value(*self.semaphore),
+                None => device.get_semaphore_counter_value(*self.semaphore),
+            };
+            self.completed.set(value?);
+            return Ok(self.completed.get());
+        }
+
+        let mut fences = self.fences.borrow_mut();
+        while let Some((value, fence)) = fences.front() {
+            if !device.get_fence_status(**fence)? {
+                break;
+            }
+
+            device.reset_fences(&[**fence])?;
+            self.completed.set(*value);
+
+            let (_, fence) = fences.pop_front().unwrap();
+            self.free_fences.borrow_mut().push(fence);
+        }
+
+        Ok(self.completed.get())
+    }
+
+    /// Returns the wait for a submit, to any queue, using the results of the
+    /// submit that signaled `value` from `stage` on. `None` if there is
+    /// nothing to wait for: the value was reached, or the host waited for it
+    /// without timeline semaphores.
+    pub unsafe fn dependency(
+        &self,
+        device: &Device,
+        data: &AppData,
+        value: u64,
+        stage: vk::PipelineStageFlags2,
+    ) -> VkResult<Option<SemaphoreSubmit>> {
+        if value <= self.completed.get() {
+            return Ok(None);
+        }
+
+        if *self.semaphore == vk::Semaphore::null() {
+            self.wait(device, data, value)?;
+            return Ok(None);
+        }
+
+        Ok(Some(SemaphoreSubmit {
+            semaphore: *self.semaphore,
+            stage,
+            value,
+        }))
+    }
+
+    /// Blocks until the queue has reached `value`.
+    pub unsafe fn wait(&self, device: &Device, data: &AppData, value: u64) -> VkResult<()> {
+        if value <= self.completed.get() {
+            return Ok(());
+        }
+
+        if *self.semaphore != vk::Semaphore::null() {
+            let semaphores = &[*self.semaphore];
+            let values = &[value];
+            let info = vk::SemaphoreWaitInfo::builder()
+                .semaphores(semaphores)
+                .values(values);
+
+            match &data.timeline_semaphore_ext {
+                Some(ext) => ext.wait_semaphores(&info, u64::MAX),
+                None => device.wait_semaphores(&info, u64::MAX),
+            }?;
+
+            self.completed.set(value);
+            return Ok(());
+        }
+
+        // Fences of a queue are signaled in submission order, the first fence
+        // at or after `value` covers it.
+        let fence = self
+            .fences
+            .borrow()
+            .iter()
+            .find(|(v, _)| *v >= value)
+            .map(|(_, fence)| **fence)
+            .expect("waiting for a value that was not submitted");
+        device.wait_for_fences(&[fence], true, u64::MAX)?;
+
+        self.completed(device, data).map(|_| ())
+    }
+}
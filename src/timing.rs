@@ -0,0 +1,324 @@
+//! GPU frame timing using timestamp queries.
+//!
+//! Every command buffer slot owns a fixed range of queries in a shared pool.
+//! Scopes are recorded together with the command buffer and their results are
+//! read back the next time the same slot is submitted, so reading never
+//! stalls on the GPU.
+//!
+//! The render graph records a scope for every pass it executes, see
+//! [`RenderGraph::timestamps`](crate::graph::RenderGraph::timestamps). The
+//! average duration of every scope is logged periodically at the `info`
+//! level, and the timings of the latest resolved frame are available from
+//! [`GpuTimer::last_report`], e.g. for an overlay.
+
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use ash::vk;
+use ash::{Device, Instance};
+
+use crate::owned::{Owned, SharedDevice};
+use crate::{debug, AppData};
+
+/// Maximum number of scopes recorded into a single command buffer.
+const MAX_SCOPES: u32 = 16;
+
+/// Interval between logged averages.
+const LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct GpuTimer {
+    query_pool: Owned<vk::QueryPool>,
+    /// Nanoseconds per timestamp tick.
+    period: f64,
+    /// Mask of the bits valid in a timestamp.
+    mask: u64,
+    slots: Vec<Slot>,
+    last_report: Option<FrameTiming>,
+    averages: Averages,
+}
+
+#[derive(Default)]
+struct Slot {
+    /// Labels of the recorded scopes, by scope index. Scopes are recorded
+    /// through a shared reference to the timer in `AppData`.
+    labels: RefCell<Vec<&'static str>>,
+    /// Frame number of the last submission whose results are not read yet.
+    pending: Option<u64>,
+}
+
+/// Identifies a scope started by [`GpuTimer::begin`].
+#[derive(Copy, Clone, Debug)]
+pub struct Scope {
+    slot: usize,
+    index: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct FrameTiming {
+    pub frame: u64,
+    pub scopes: Vec<(&'static str, Duration)>,
+}
+
+impl GpuTimer {
+    /// Creates a timer with `slots` independent command buffer slots.
+    ///
+    /// Returns `None` if the graphics queue does not support timestamps.
+    pub unsafe fn new(
+        instance: &Instance,
+        device: &SharedDevice,
+        data: &AppData,
+        slots: usize,
+    ) -> Option<Self> {
+        let properties = instance.get_physical_device_properties(data.physical_device);
+        let valid_bits = data.queues.graphics.timestamp_valid_bits;
+        if valid_bits == 0 {
+            tracing::warn!("graphics queue does not support timestamps");
+            return None;
+        }
+
+        let info = vk::QueryPoolCreateInfo::builder()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(slots as u32 * MAX_SCOPES * 2);
+
+        let query_pool = Owned::new(device, device.create_query_pool(&info, None).unwrap());
+        debug::set_object_name(device, data, *query_pool, "timestamp queries");
+
+        let mask = if valid_bits >= 64 {
+            u64::MAX
+        } else {
+            (1 << valid_bits) - 1
+        };
+
+        Some(Self {
+            query_pool,
+            period: properties.limits.timestamp_period as f64,
+            mask,
+            slots: (0..slots).map(|_| Slot::default()).collect(),
+            last_report: None,
+            averages: Averages::default(),
+        })
+    }
+
+    /// Resets the queries of `slot`. Must be recorded outside of a render
+    /// pass before any scope of the slot.
+    pub unsafe fn reset(&self, device: &Device, command_buffer: vk::CommandBuffer, slot: usize) {
+        device.cmd_reset_query_pool(
+            command_buffer,
+            *self.query_pool,
+            slot as u32 * MAX_SCOPES * 2,
+            MAX_SCOPES * 2,
+        );
+
+        self.slots[slot].labels.borrow_mut().clear();
+    }
+
+    pub unsafe fn begin(
+        &self,
+        device: &Device,
+        command_buffer: vk::CommandBuffer,
+        slot: usize,
+        label: &'static str,
+    ) -> Scope {
+        let mut labels = self.slots[slot].labels.borrow_mut();
+        assert!(
+            (labels.len() as u32) < MAX_SCOPES,
+            "too many timestamp scopes"
+        );
+
+        let scope = Scope {
+            slot,
+            index: labels.len() as u32,
+        };
+        labels.push(label);
+        drop(labels);
+
+        device.cmd_write_timestamp(
+            command_buffer,
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            *self.query_pool,
+            self.query(scope),
+        );
+
+        scope
+    }
+
+    pub unsafe fn end(&self, device: &Device, command_buffer: vk::CommandBuffer, scope: Scope) {
+        device.cmd_write_timestamp(
+            command_buffer,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            *self.query_pool,
+            self.query(scope) + 1,
+        );
+    }
+
+    /// Called before `slot` is submitted for `frame`.
+    ///
+    /// Resolves the results of the previous submission of the slot, if any.
+    pub unsafe fn submit(&mut self, device: &Device, slot: usize, frame: u64) {
+        if let Some(previous) = self.slots[slot].pending.take() {
+            self.resolve(device, slot, previous);
+        }
+
+        self.slots[slot].pending = Some(frame);
+    }
+
+    unsafe fn resolve(&mut self, device: &Device, slot: usize, frame: u64) {
+        let labels = self.slots[slot].labels.borrow();
+        if labels.is_empty() {
+            return;
+        }
+
+        let mut timestamps = vec![0u64; labels.len() * 2];
+        match device.get_query_pool_results(
+            *self.query_pool,
+            slot as u32 * MAX_SCOPES * 2,
+            timestamps.len() as u32,
+            &mut timestamps,
+            vk::QueryResultFlags::TYPE_64,
+        ) {
+            Ok(()) => (),
+            Err(vk::Result::NOT_READY) => {
+                tracing::trace!("timestamps of frame {} not ready", frame);
+                return;
+            }
+            Err(err) => panic!("failed to read timestamps: {}", err),
+        }
+
+        let scopes = labels
+            .iter()
+            .zip(timestamps.chunks_exact(2))
+            .map(|(label, ts)| (*label, self.duration(ts[0], ts[1])))
+            .collect::<Vec<_>>();
+        drop(labels);
+
+        self.record(FrameTiming { frame, scopes });
+    }
+
+    /// Returns the time between the timestamps `start` and `end`, which may
+    /// have wrapped around.
+    fn duration(&self, start: u64, end: u64) -> Duration {
+        let ticks = (end & self.mask).wrapping_sub(start & self.mask) & self.mask;
+        Duration::from_nanos((ticks as f64 * self.period) as u64)
+    }
+
+    fn record(&mut self, report: FrameTiming) {
+        tracing::trace!("{}", report);
+
+        self.averages.add(&report);
+        self.last_report = Some(report);
+    }
+
+    /// Returns the timings of the most recently resolved frame.
+    #[allow(dead_code)]
+    pub fn last_report(&self) -> Option<&FrameTiming> {
+        self.last_report.as_ref()
+    }
+
+    fn query(&self, scope: Scope) -> u32 {
+        (scope.slot as u32 * MAX_SCOPES + scope.index) * 2
+    }
+}
+
+/// Scope durations since the averages were last logged.
+#[derive(Default)]
+struct Averages {
+    /// Start of the current interval.
+    since: Option<Instant>,
+    frames: u32,
+    /// Total duration by label, in order of first appearance.
+    totals: Vec<(&'static str, Duration)>,
+}
+
+impl Averages {
+    fn add(&mut self, report: &FrameTiming) {
+        for (label, duration) in &report.scopes {
+            match self.totals.iter_mut().find(|(l, _)| l == label) {
+                Some((_, total)) => *total += *duration,
+                None => self.totals.push((label, *duration)),
+            }
+        }
+        self.frames += 1;
+
+        let now = Instant::now();
+        let since = *self.since.get_or_insert(now);
+        if now - since >= LOG_INTERVAL {
+            let scopes = self
+                .totals
+                .iter()
+                .map(|(label, total)| {
+                    let average = total.as_secs_f64() * 1000.0 / self.frames as f64;
+                    format!("{} {:.3} ms", label, average)
+                })
+                .collect::<Vec<_>>();
+            tracing::info!(
+                "gpu time over {} frames: {}",
+                self.frames,
+                scopes.join(", ")
+            );
+
+            *self = Self {
+                since: Some(now),
+                ..Default::default()
+            };
+        }
+    }
+}
+
+impl std::fmt::Display for FrameTiming {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "gpu frame {}:", self.frame)?;
+        for (label, duration) in &self.scopes {
+            write!(f, " {}={:.3}ms", label, duration.as_secs_f64() * 1000.0)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer(valid_bits: u32, period: f64) -> GpuTimer {
+        GpuTimer {
+            query_pool: Owned::default(),
+            period,
+            mask: (1 << valid_bits) - 1,
+            slots: vec![],
+            last_report: None,
+            averages: Averages::default(),
+        }
+    }
+
+    #[test]
+    fn duration() {
+        let timer = timer(36, 2.0);
+        assert_eq!(timer.duration(100, 600), Duration::from_nanos(1000));
+
+        // Bits above the valid ones are ignored and the counter may wrap.
+        let max = (1 << 36) - 1;
+        assert_eq!(timer.duration(max - 9, 10), Duration::from_nanos(40));
+        assert_eq!(
+            timer.duration(1 << 40 | 5, 1 << 37 | 15),
+            Duration::from_nanos(20)
+        );
+    }
+
+    #[test]
+    fn last_report() {
+        let mut timer = timer(36, 1.0);
+        assert!(timer.last_report().is_none());
+
+        for frame in 1..=2 {
+            timer.record(FrameTiming {
+                frame,
+                scopes: vec![("scene", Duration::from_micros(frame * 100))],
+            });
+        }
+
+        let report = timer.last_report().unwrap();
+        assert_eq!(report.frame, 2);
+        assert_eq!(report.scopes, [("scene", Duration::from_micros(200))]);
+        assert_eq!(timer.averages.frames, 2);
+    }
+}
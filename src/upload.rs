@@ -0,0 +1,115 @@
+//! Uploads of buffer contents through the transfer queue.
+//!
+//! The contents are written to a host visible staging buffer and copied into
+//! a device local buffer by a submit to the transfer timeline, which runs on
+//! the dedicated transfer queue if there is one. Submits to other queues
+//! using the buffer wait for the upload with
+//! [`Timeline::dependency`](crate::timeline::Timeline::dependency).
+
+use ash::vk;
+use ash::Instance;
+
+use crate::debug;
+use crate::memory::create_buffer;
+use crate::owned::{Owned, SharedDevice};
+use crate::AppData;
+
+/// A device local buffer filled by [`upload_buffer`].
+#[derive(Default)]
+pub struct UploadedBuffer {
+    pub buffer: Owned<vk::Buffer>,
+    _memory: Owned<vk::DeviceMemory>,
+    /// Transfer timeline value signaled once the contents are available.
+    pub value: u64,
+}
+
+/// Objects used by an upload until the transfer timeline reaches its value.
+pub struct Staging {
+    _command_pool: Owned<vk::CommandPool>,
+    _buffer: Owned<vk::Buffer>,
+    _memory: Owned<vk::DeviceMemory>,
+}
+
+/// Creates a buffer usable by the graphics queue and submits the upload of
+/// `contents` into it.
+///
+/// The staging objects must be kept until the upload has completed.
+pub unsafe fn upload_buffer(
+    instance: &Instance,
+    device: &SharedDevice,
+    data: &AppData,
+    contents: &[u8],
+    usage: vk::BufferUsageFlags,
+    name: &str,
+) -> (UploadedBuffer, Staging) {
+    let size = contents.len() as vk::DeviceSize;
+
+    let (staging_buffer, staging_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        &[],
+    );
+    let memory = device
+        .map_memory(*staging_memory, 0, size, vk::MemoryMapFlags::empty())
+        .unwrap();
+    std::ptr::copy_nonoverlapping(contents.as_ptr(), memory.cast(), contents.len());
+    device.unmap_memory(*staging_memory);
+
+    // Shared with the graphics queue instead of transferring the ownership.
+    let (buffer, buffer_memory) = create_buffer(
+        instance,
+        device,
+        data,
+        size,
+        usage | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        &[data.queues.transfer.family, data.queues.graphics.family],
+    );
+    debug::set_object_name(device, data, *buffer, name);
+
+    let info = vk::CommandPoolCreateInfo::builder()
+        .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+        .queue_family_index(data.queues.transfer.family);
+    let command_pool = Owned::new(device, device.create_command_pool(&info, None).unwrap());
+
+    let info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(*command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let command_buffer = device.allocate_command_buffers(&info).unwrap()[0];
+
+    let info =
+        vk::CommandBufferBeginInfo::builder().flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(command_buffer, &info).unwrap();
+    debug::begin_label(data, command_buffer, "upload");
+
+    let region = vk::BufferCopy::builder().size(size).build();
+    device.cmd_copy_buffer(command_buffer, *staging_buffer, *buffer, &[region]);
+
+    debug::end_label(data, command_buffer);
+    device.end_command_buffer(command_buffer).unwrap();
+
+    // The dependency of the waiting submit makes the copy available.
+    let value = data
+        .transfer_timeline
+        .submit(device, data, &[], &[command_buffer], &[])
+        .unwrap();
+    data.debug_state.check();
+
+    (
+        UploadedBuffer {
+            buffer,
+            _memory: buffer_memory,
+            value,
+        },
+        Staging {
+            _command_pool: command_pool,
+            _buffer: staging_buffer,
+            _memory: staging_memory,
+        },
+    )
+}
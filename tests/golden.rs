@@ -0,0 +1,185 @@
+//! Golden image tests.
+//!
+//! Every scene is rendered offscreen by `hello-vulkan render` and compared
+//! against `tests/golden/<scene>.png`. Meant to run on a software
+//! implementation such as lavapipe, so that results are reproducible. The test
+//! needs a Vulkan implementation and is ignored by default:
+//!
+//! ```text
+//! VK_ICD_FILENAMES=/usr/share/vulkan/icd.d/lvp_icd.x86_64.json cargo test --test golden -- --ignored
+//! ```
+//!
+//! It fails if no Vulkan device is available or a golden image is missing.
+//! Set `UPDATE_GOLDEN=1` to bless the current output as the golden images. On
+//! mismatch the rendered image and a diff image are written to
+//! `target/tmp/golden/`.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Maximum allowed difference per channel.
+const TOLERANCE: u8 = 2;
+
+/// `(scene, width, height)`, see `scene::Scene`.
+const SCENES: &[(&str, u32, u32)] = &[("triangle", 800, 600), ("grid", 256, 256)];
+
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+fn read_png(path: &Path) -> Image {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!(info.color_type, png::ColorType::Rgba);
+    assert_eq!(info.bit_depth, png::BitDepth::Eight);
+    pixels.truncate(info.buffer_size());
+
+    Image {
+        width: info.width,
+        height: info.height,
+        pixels,
+    }
+}
+
+fn write_png(path: &Path, image: &Image) {
+    let file = File::create(path).unwrap();
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), image.width, image.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&image.pixels).unwrap();
+}
+
+/// Returns `false` if there is no Vulkan implementation to render with.
+fn vulkan_available() -> bool {
+    let Ok(entry) = (unsafe { ash::Entry::load() }) else {
+        return false;
+    };
+
+    let info = ash::vk::InstanceCreateInfo::default();
+    let Ok(instance) = (unsafe { entry.create_instance(&info, None) }) else {
+        return false;
+    };
+
+    let available = unsafe { instance.enumerate_physical_devices() }
+        .map(|devices| !devices.is_empty())
+        .unwrap_or(false);
+
+    unsafe { instance.destroy_instance(None) };
+    available
+}
+
+/// Returns the number of pixels differing by more than [`TOLERANCE`] and an
+/// image highlighting them in red.
+fn compare(expected: &Image, actual: &Image) -> (usize, Image) {
+    let mut mismatches = 0;
+    let mut diff = Vec::with_capacity(actual.pixels.len());
+
+    for (e, a) in expected
+        .pixels
+        .chunks_exact(4)
+        .zip(actual.pixels.chunks_exact(4))
+    {
+        if e.iter().zip(a).any(|(e, a)| e.abs_diff(*a) > TOLERANCE) {
+            mismatches += 1;
+            diff.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            diff.extend_from_slice(&[a[0] / 4, a[1] / 4, a[2] / 4, 255]);
+        }
+    }
+
+    (
+        mismatches,
+        Image {
+            width: actual.width,
+            height: actual.height,
+            pixels: diff,
+        },
+    )
+}
+
+#[test]
+#[ignore = "needs a Vulkan device, run with --ignored"]
+fn golden_images() {
+    assert!(
+        vulkan_available(),
+        "no Vulkan device available, set VK_ICD_FILENAMES to use lavapipe"
+    );
+
+    let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let output_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&output_dir).unwrap();
+
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    if update {
+        std::fs::create_dir_all(&golden_dir).unwrap();
+    }
+
+    let mut failures = vec![];
+    for (name, width, height) in SCENES {
+        let actual_path = output_dir.join(format!("{}.png", name));
+
+        // Fail on any validation error.
+        let status = Command::new(env!("CARGO_BIN_EXE_hello-vulkan"))
+            .env("HELLO_VULKAN_VALIDATION", "count")
+            .arg("--scene")
+            .arg(name)
+            .arg("render")
+            .arg(&actual_path)
+            .arg(width.to_string())
+            .arg(height.to_string())
+            .status()
+            .unwrap();
+        assert!(status.success(), "rendering {} failed: {}", name, status);
+
+        let golden_path = golden_dir.join(format!("{}.png", name));
+        if update {
+            std::fs::copy(&actual_path, &golden_path).unwrap();
+            continue;
+        }
+
+        if !golden_path.exists() {
+            failures.push(format!(
+                "{}: no golden image, run with UPDATE_GOLDEN=1 to create it",
+                name
+            ));
+            continue;
+        }
+
+        let expected = read_png(&golden_path);
+        let actual = read_png(&actual_path);
+
+        if (expected.width, expected.height) != (actual.width, actual.height) {
+            failures.push(format!(
+                "{}: size mismatch, expected {}x{}, got {}x{}",
+                name, expected.width, expected.height, actual.width, actual.height
+            ));
+            continue;
+        }
+
+        let (mismatches, diff) = compare(&expected, &actual);
+        if mismatches != 0 {
+            let diff_path = output_dir.join(format!("{}.diff.png", name));
+            write_png(&diff_path, &diff);
+
+            failures.push(format!(
+                "{}: {} pixels differ, see {} and {}",
+                name,
+                mismatches,
+                actual_path.display(),
+                diff_path.display()
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}